@@ -0,0 +1,82 @@
+use brunhild::Appender;
+use criterion::{
+	black_box, criterion_group, criterion_main, BenchmarkId, Criterion,
+};
+use std::fmt::Write;
+
+// Fragments, like the ones written when rendering elements, attributes and
+// text
+static FRAGMENTS: [&str; 8] = [
+	"<div",
+	" id=\"bh-1234\"",
+	" class=\"item active\"",
+	">",
+	"<span>",
+	"Lorem ipsum dolor sit amet, consectetur adipiscing elit",
+	"</span>",
+	"</div>",
+];
+
+// Output sizes of typical renders from single elements to whole pages
+static SIZES: [usize; 4] = [256, 4 << 10, 64 << 10, 1 << 20];
+
+fn write_fragments<W: Write>(w: &mut W, size: usize) {
+	let mut written = 0;
+	while written < size {
+		for f in FRAGMENTS.iter() {
+			w.write_str(f).unwrap();
+			written += f.len();
+		}
+	}
+}
+
+fn render(c: &mut Criterion) {
+	let mut g = c.benchmark_group("render");
+	for size in SIZES.iter() {
+		g.bench_with_input(BenchmarkId::new("string", size), size, |b, s| {
+			b.iter(|| {
+				let mut w = String::new();
+				write_fragments(&mut w, *s);
+				black_box(w)
+			})
+		});
+		g.bench_with_input(BenchmarkId::new("appender", size), size, |b, s| {
+			b.iter(|| {
+				let mut w = Appender::new();
+				write_fragments(&mut w, *s);
+				black_box(w.into_string())
+			})
+		});
+	}
+	g.finish();
+}
+
+// Buffers reused between renders, like when flushing batches of keyed children
+fn render_reused(c: &mut Criterion) {
+	let mut g = c.benchmark_group("render_reused");
+	for size in SIZES.iter() {
+		g.bench_with_input(BenchmarkId::new("string", size), size, |b, s| {
+			let mut w = String::new();
+			b.iter(|| {
+				w.clear();
+				write_fragments(&mut w, *s);
+				black_box(w.len())
+			})
+		});
+		g.bench_with_input(BenchmarkId::new("appender", size), size, |b, s| {
+			let mut w = Appender::new();
+			let mut dst = String::new();
+			b.iter(|| {
+				w.clear();
+				write_fragments(&mut w, *s);
+				dst.clear();
+				w.dump_into(&mut dst);
+				black_box(dst.len())
+			})
+		});
+	}
+	g.finish();
+}
+
+criterion_group!(benches, render, render_reused);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+use brunhild::tokenizer::{lookup_predefined, PREDEFINED};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+// Mix of predefined hits and misses, like tags and attribute keys in a typical
+// render
+static KEYS: [&str; 12] = [
+	"div",
+	"span",
+	"class",
+	"a",
+	"href",
+	"input",
+	"type",
+	"xmp",
+	"data-id",
+	"aria-label",
+	"my-component",
+	"onclick",
+];
+
+fn binary_search(s: &str) -> Option<u32> {
+	PREDEFINED.binary_search(&s).ok().map(|i| i as u32 + 1)
+}
+
+fn predefined_lookup(c: &mut Criterion) {
+	let mut g = c.benchmark_group("predefined_lookup");
+	g.bench_function("binary_search", |b| {
+		b.iter(|| {
+			for k in KEYS.iter() {
+				black_box(binary_search(black_box(k)));
+			}
+		})
+	});
+	g.bench_function("perfect_hash", |b| {
+		b.iter(|| {
+			for k in KEYS.iter() {
+				black_box(lookup_predefined(black_box(k)));
+			}
+		})
+	});
+	g.finish();
+}
+
+criterion_group!(benches, predefined_lookup);
+criterion_main!(benches);
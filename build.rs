@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+include!("src/predefined/hash.rs");
+
+// Lists of predefined strings, that are converted into tokens without any
+// registry lookups. Each line is one string and the lists must be sorted and
+// free of duplicates.
+static LISTS: [(&str, &str, &str); 2] = [
+	("tag", "src/predefined/tags.txt", "Predefined HTML tag tokens"),
+	("attr", "src/predefined/attrs.txt", "Predefined HTML attribute tokens"),
+];
+
+fn main() {
+	let mut modules = Vec::with_capacity(LISTS.len());
+	let mut all = BTreeMap::<String, u32>::new();
+	for (_, path, _) in LISTS.iter() {
+		println!("cargo:rerun-if-changed={}", path);
+		let list = read_list(path);
+		for s in list.iter() {
+			all.insert(s.clone(), 0);
+		}
+		modules.push(list);
+	}
+
+	// Token 0 is reserved for the empty string
+	for (i, token) in all.values_mut().enumerate() {
+		*token = i as u32 + 1;
+	}
+
+	let mut w = String::new();
+	writeln!(
+		w,
+		"// Sorted list of predefined HTML tags and attributes. Generated by \
+		 build.rs.\n#[doc(hidden)]\npub static PREDEFINED: [&str; {}] = [",
+		all.len()
+	)
+	.unwrap();
+	for s in all.keys() {
+		writeln!(w, "\t{:?},", s).unwrap();
+	}
+	w += "];\n";
+
+	write_phf(&mut w, all.keys().map(|s| s.as_str()).collect());
+
+	for ((module, _, doc), list) in LISTS.iter().zip(modules.iter()) {
+		writeln!(
+			w,
+			"\n// {}\npub mod {} {{\n\tuse super::Token;\n",
+			doc, module
+		)
+		.unwrap();
+		for s in list.iter() {
+			writeln!(
+				w,
+				"\tpub const {}: Token = Token({});",
+				const_name(s),
+				all[s]
+			)
+			.unwrap();
+		}
+		w += "}\n";
+	}
+
+	fs::write(
+		Path::new(&env::var("OUT_DIR").unwrap()).join("predefined.rs"),
+		w,
+	)
+	.unwrap();
+}
+
+// Generate perfect hash table of predefined strings to their tokens.
+// Tokens are the 1-based indexes of the sorted keys.
+fn write_phf(w: &mut String, keys: Vec<&str>) {
+	let (seed, displacements, slots) = phf_build(&keys);
+	writeln!(
+		w,
+		"\n// Perfect hash table of predefined strings to tokens. Generated \
+		 by build.rs.\nconst PHF_SEED: u64 = {};\n\
+		 static PHF_DISPLACEMENTS: [(u32, u32); {}] = {:?};\n\
+		 static PHF_SLOTS: [u16; {}] = {:?};",
+		seed,
+		displacements.len(),
+		displacements,
+		slots.len(),
+		slots,
+	)
+	.unwrap();
+}
+
+// Read and validate list of predefined strings
+fn read_list(path: &str) -> Vec<String> {
+	let list: Vec<String> = fs::read_to_string(path)
+		.unwrap_or_else(|e| panic!("{}: {}", path, e))
+		.lines()
+		.map(|l| l.trim())
+		.filter(|l| !l.is_empty())
+		.map(String::from)
+		.collect();
+
+	for (i, s) in list.iter().enumerate() {
+		if !s
+			.bytes()
+			.all(|b| matches!(b, b'a'..=b'z' | b'0'..=b'9' | b'-'))
+		{
+			panic!("{}: invalid predefined string: {:?}", path, s);
+		}
+		if i != 0 && list[i - 1] >= *s {
+			panic!(
+				"{}: list not sorted or contains duplicates: {:?} >= {:?}",
+				path,
+				list[i - 1],
+				s
+			);
+		}
+	}
+
+	list
+}
+
+// Convert predefined string to constant name
+fn const_name(s: &str) -> String {
+	s.to_uppercase().replace('-', "_")
+}
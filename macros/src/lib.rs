@@ -0,0 +1,652 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{quote, quote_spanned};
+use std::collections::BTreeMap;
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::{braced, Expr, Ident, LitStr, Pat, Token};
+
+// Builds a Node tree from JSX-like syntax.
+//
+// ```ignore
+// html! {
+// 	<ul class="list" key={id}>
+// 		"literal text"
+// 		{ some_node_or_string }
+// 		for item in items.iter() {
+// 			<li data-id={item.id}>{ &item.name }</li>
+// 		}
+// 		if show_footer {
+// 			<br/>
+// 		} else {
+// 			<span>"no footer"</span>
+// 		}
+// 	</ul>
+// }
+// ```
+//
+// Literal text is HTML-escaped at compile time. Expressions in braces are
+// converted with Node::from(). Attribute values in braces are converted with
+// StrOrToken::from(&value). The "key" attribute sets ElementOptions::key.
+// Predefined tags and attribute keys are tokenized at compile time. Elements
+// without any expressions in them or their subtree are prerendered to static
+// HTML, that is never rebuilt or diffed. Their attributes are written in the
+// same order as at runtime, falling back to building the element, if this
+// order depends on tokens of non-predefined names.
+//
+// The expansion refers to items of the brunhild crate by the absolute path
+// ::brunhild, so the crate must not be renamed in the dependent's Cargo.toml.
+#[proc_macro]
+pub fn html(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	match syn::parse::<Root>(input) {
+		Ok(root) => root.0.to_tokens(),
+		Err(e) => e.to_compile_error(),
+	}
+	.into()
+}
+
+// Single root node of an html! invocation
+struct Root(Child);
+
+impl Parse for Root {
+	fn parse(input: ParseStream) -> syn::Result<Self> {
+		if input.is_empty() {
+			return Err(input.error("expected a root node"));
+		}
+		let root = Child::parse(input)?;
+		if !input.is_empty() {
+			return Err(input.error("html! must contain exactly one root node"));
+		}
+		match root {
+			Child::For { for_token, .. } => Err(syn::Error::new(
+				for_token.span,
+				"loops are only allowed inside elements",
+			)),
+			Child::If { if_token, .. } => Err(syn::Error::new(
+				if_token.span,
+				"conditionals are only allowed inside elements",
+			)),
+			root => Ok(Root(root)),
+		}
+	}
+}
+
+// Element, text or control flow inside an element
+enum Child {
+	Element(Element),
+	Text(LitStr),
+	Expr(Expr),
+	For {
+		for_token: Token![for],
+		pat: Pat,
+		expr: Expr,
+		body: Vec<Child>,
+	},
+	If {
+		if_token: Token![if],
+		cond: Expr,
+		then: Vec<Child>,
+		otherwise: Option<Else>,
+	},
+}
+
+// Else branch of a conditional
+enum Else {
+	If(Box<Child>),
+	Block(Vec<Child>),
+}
+
+impl Parse for Child {
+	fn parse(input: ParseStream) -> syn::Result<Self> {
+		let look = input.lookahead1();
+		if look.peek(Token![<]) {
+			Ok(Child::Element(input.parse()?))
+		} else if look.peek(LitStr) {
+			Ok(Child::Text(input.parse()?))
+		} else if look.peek(syn::token::Brace) {
+			let content;
+			braced!(content in input);
+			Ok(Child::Expr(content.parse()?))
+		} else if look.peek(Token![for]) {
+			Ok(Child::For {
+				for_token: input.parse()?,
+				pat: Pat::parse_multi_with_leading_vert(input)?,
+				expr: {
+					input.parse::<Token![in]>()?;
+					Expr::parse_without_eager_brace(input)?
+				},
+				body: parse_block(input)?,
+			})
+		} else if look.peek(Token![if]) {
+			Ok(Child::If {
+				if_token: input.parse()?,
+				cond: Expr::parse_without_eager_brace(input)?,
+				then: parse_block(input)?,
+				otherwise: if input.peek(Token![else]) {
+					input.parse::<Token![else]>()?;
+					Some(if input.peek(Token![if]) {
+						Else::If(Box::new(input.parse()?))
+					} else {
+						Else::Block(parse_block(input)?)
+					})
+				} else {
+					None
+				},
+			})
+		} else {
+			Err(look.error())
+		}
+	}
+}
+
+// Parse braced list of children
+fn parse_block(input: ParseStream) -> syn::Result<Vec<Child>> {
+	let content;
+	braced!(content in input);
+	let mut children = Vec::new();
+	while !content.is_empty() {
+		children.push(content.parse()?);
+	}
+	Ok(children)
+}
+
+// Hyphenated tag or attribute name
+struct Name {
+	value: String,
+	span: Span,
+}
+
+impl Parse for Name {
+	fn parse(input: ParseStream) -> syn::Result<Self> {
+		let first = Ident::parse_any(input)?;
+		let mut value = first.to_string();
+		while input.peek(Token![-]) {
+			input.parse::<Token![-]>()?;
+			value.push('-');
+			value += &Ident::parse_any(input)?.to_string();
+		}
+		Ok(Name {
+			value,
+			span: first.span(),
+		})
+	}
+}
+
+// Attribute of an element
+struct Attr {
+	name: Name,
+	value: AttrValue,
+}
+
+enum AttrValue {
+	Lit(LitStr),
+	Expr(Expr),
+}
+
+impl Parse for Attr {
+	fn parse(input: ParseStream) -> syn::Result<Self> {
+		let name: Name = input.parse()?;
+		let value = if input.peek(Token![=]) {
+			input.parse::<Token![=]>()?;
+			if input.peek(LitStr) {
+				AttrValue::Lit(input.parse()?)
+			} else {
+				let content;
+				braced!(content in input);
+				AttrValue::Expr(content.parse()?)
+			}
+		} else {
+			// Boolean attribute
+			AttrValue::Lit(LitStr::new("", name.span))
+		};
+		Ok(Attr { name, value })
+	}
+}
+
+struct Element {
+	tag: Name,
+	key: Option<Expr>,
+	attrs: Vec<Attr>,
+	children: Vec<Child>,
+}
+
+impl Parse for Element {
+	fn parse(input: ParseStream) -> syn::Result<Self> {
+		input.parse::<Token![<]>()?;
+		let tag: Name = input.parse()?;
+
+		let mut key = None;
+		let mut attrs = Vec::new();
+		while !input.peek(Token![>]) && !input.peek(Token![/]) {
+			if input.is_empty() {
+				return Err(syn::Error::new(
+					tag.span,
+					format!("unclosed tag `<{}`", tag.value),
+				));
+			}
+			let attr: Attr = input.parse()?;
+			if attr.name.value == "key" {
+				key = Some(match attr.value {
+					AttrValue::Expr(expr) => expr,
+					AttrValue::Lit(lit) => {
+						return Err(syn::Error::new(
+							lit.span(),
+							"key must be an expression",
+						))
+					}
+				});
+			} else {
+				attrs.push(attr);
+			}
+		}
+
+		let mut el = Element {
+			tag,
+			key,
+			attrs,
+			children: Vec::new(),
+		};
+
+		// Self-closing tag
+		if input.peek(Token![/]) {
+			input.parse::<Token![/]>()?;
+			input.parse::<Token![>]>()?;
+			return Ok(el);
+		}
+		input.parse::<Token![>]>()?;
+
+		loop {
+			if input.is_empty() {
+				return Err(syn::Error::new(
+					el.tag.span,
+					format!("unclosed tag `<{}>`", el.tag.value),
+				));
+			}
+			if input.peek(Token![<]) && input.peek2(Token![/]) {
+				break;
+			}
+			el.children.push(input.parse()?);
+		}
+
+		input.parse::<Token![<]>()?;
+		input.parse::<Token![/]>()?;
+		let closing: Name = input.parse()?;
+		if closing.value != el.tag.value {
+			return Err(syn::Error::new(
+				closing.span,
+				format!(
+					"expected closing tag `</{}>`, found `</{}>`",
+					el.tag.value, closing.value
+				),
+			));
+		}
+		input.parse::<Token![>]>()?;
+
+		Ok(el)
+	}
+}
+
+// Expression converting a literal name to a StrOrToken. Evaluated in a
+// constant, so names in the tokenizer's predefined set are resolved to their
+// tokens at compile time.
+fn name_tokens(name: &Name) -> TokenStream {
+	let lit = LitStr::new(&name.value, name.span);
+	quote_spanned! {name.span=>
+		{
+			const NAME: ::brunhild::tokenizer::StrOrToken<'static> =
+				::brunhild::tokenizer::StrOrToken::literal(#lit);
+			NAME
+		}
+	}
+}
+
+// Escape HTML special characters the same way as the runtime escaping of
+// brunhild does
+fn html_escape(s: &str) -> String {
+	let mut w = String::with_capacity(s.len());
+	for ch in s.chars() {
+		match ch {
+			'&' => w += "&amp;",
+			'\'' => w += "&#39;",
+			'<' => w += "&lt;",
+			'>' => w += "&gt;",
+			'"' => w += "&#34;",
+			_ => w.push(ch),
+		}
+	}
+	w
+}
+
+// Escape an attribute value the same way as the runtime attribute writing of
+// brunhild does
+fn attr_escape(s: &str) -> String {
+	let mut w = String::with_capacity(s.len());
+	for ch in s.chars() {
+		match ch {
+			'&' => w += "&amp;",
+			'"' => w += "&#34;",
+			'<' => w += "&lt;",
+			_ => w.push(ch),
+		}
+	}
+	w
+}
+
+impl Child {
+	// Expression building a Node. Only valid for non-control flow children.
+	fn to_tokens(&self) -> TokenStream {
+		match self {
+			Child::Element(el) => el.to_tokens(),
+			Child::Text(lit) => {
+				let text = LitStr::new(&html_escape(&lit.value()), lit.span());
+				quote_spanned! {lit.span()=>
+					::brunhild::Node::text(&::brunhild::TextOptions {
+						text: #text,
+						escape: false,
+						..Default::default()
+					})
+				}
+			}
+			Child::Expr(expr) => quote! { ::brunhild::Node::from(#expr) },
+			Child::For { .. } | Child::If { .. } => unreachable!(),
+		}
+	}
+
+	// Statements pushing the child's nodes to __children
+	fn to_statements(&self) -> TokenStream {
+		match self {
+			Child::For {
+				pat, expr, body, ..
+			} => {
+				let body = body.iter().map(Child::to_statements);
+				quote! {
+					for #pat in #expr {
+						#(#body)*
+					}
+				}
+			}
+			Child::If {
+				cond,
+				then,
+				otherwise,
+				..
+			} => {
+				let then = then.iter().map(Child::to_statements);
+				let otherwise = otherwise.as_ref().map(|e| match e {
+					Else::If(child) => {
+						let child = child.to_statements();
+						quote! { else #child }
+					}
+					Else::Block(children) => {
+						let children =
+							children.iter().map(Child::to_statements);
+						quote! { else { #(#children)* } }
+					}
+				});
+				quote! {
+					if #cond {
+						#(#then)*
+					} #otherwise
+				}
+			}
+			child => {
+				let node = child.to_tokens();
+				quote! { __children.push(#node); }
+			}
+		}
+	}
+}
+
+impl Element {
+	// Returns, if the element and its subtree contain no expressions or control
+	// flow
+	fn is_static(&self) -> bool {
+		self.key.is_none()
+			&& self
+				.attrs
+				.iter()
+				.all(|a| matches!(a.value, AttrValue::Lit(_)))
+			&& self.children.iter().all(|ch| match ch {
+				Child::Element(el) => el.is_static(),
+				Child::Text(_) => true,
+				_ => false,
+			})
+	}
+
+	// Literal attributes sorted by name and deduplicated, keeping the last
+	// value, like Attrs does at runtime
+	fn static_attrs(&self) -> BTreeMap<&str, String> {
+		self.attrs
+			.iter()
+			.filter_map(|a| match &a.value {
+				AttrValue::Lit(lit) => {
+					Some((a.name.value.as_str(), lit.value()))
+				}
+				AttrValue::Expr(_) => None,
+			})
+			.collect()
+	}
+
+	// Push constant expressions checking, that the attributes of the static
+	// element and its subtree sorted by name are also sorted by token.
+	// Otherwise the runtime would write them in a different order.
+	fn push_order_checks(&self, checks: &mut Vec<TokenStream>) {
+		let attrs = self.static_attrs();
+		if attrs.len() > 1 {
+			let names = attrs.keys();
+			checks.push(quote! {
+				::brunhild::tokenizer::in_token_order(&[#(#names),*])
+			});
+		}
+		for ch in self.children.iter() {
+			if let Child::Element(el) = ch {
+				el.push_order_checks(checks);
+			}
+		}
+	}
+
+	// Render static element and subtree to HTML the same way Node does, but
+	// without element IDs
+	fn write_static(&self, w: &mut String) {
+		w.push('<');
+		*w += &self.tag.value;
+		for (name, value) in self.static_attrs() {
+			w.push(' ');
+			*w += name;
+			if !value.is_empty() {
+				*w += "=\"";
+				*w += &attr_escape(&value);
+				w.push('"');
+			}
+		}
+		w.push('>');
+
+		// Void elements like <br> must not be closed
+		if matches!(
+			self.tag.value.as_str(),
+			"area"
+				| "base" | "br"
+				| "col" | "embed"
+				| "hr" | "img"
+				| "input" | "keygen"
+				| "link" | "meta"
+				| "param" | "source"
+				| "track" | "wbr"
+		) {
+			return;
+		}
+		for ch in self.children.iter() {
+			match ch {
+				Child::Element(el) => el.write_static(w),
+				Child::Text(lit) => {
+					*w += "<span>";
+					*w += &html_escape(&lit.value());
+					*w += "</span>";
+				}
+				_ => unreachable!(),
+			}
+		}
+		*w += "</";
+		*w += &self.tag.value;
+		w.push('>');
+	}
+
+	fn to_tokens(&self) -> TokenStream {
+		if !self.is_static() {
+			return self.to_dynamic_tokens();
+		}
+
+		let mut html = String::new();
+		self.write_static(&mut html);
+		let html = LitStr::new(&html, self.tag.span);
+		let id_at = 1 + self.tag.value.len();
+		let hoisted = quote! { ::brunhild::Node::hoisted(#html, #id_at) };
+
+		// Attribute order of non-predefined names is only known at runtime.
+		// Fall back to building the subtree, if it can differ.
+		let mut checks = Vec::new();
+		self.push_order_checks(&mut checks);
+		if checks.is_empty() {
+			return hoisted;
+		}
+		let dynamic = self.to_dynamic_tokens();
+		quote! {{
+			const HOISTED: bool = #(#checks)&&*;
+			if HOISTED {
+				#hoisted
+			} else {
+				#dynamic
+			}
+		}}
+	}
+
+	fn to_dynamic_tokens(&self) -> TokenStream {
+		let tag = name_tokens(&self.tag);
+		let key = match &self.key {
+			Some(key) => quote! { Some(#key) },
+			None => quote! { None },
+		};
+		let attrs = self.attrs.iter().map(|a| {
+			let name = name_tokens(&a.name);
+			let value = match &a.value {
+				AttrValue::Lit(lit) => {
+					quote! { ::brunhild::tokenizer::StrOrToken::Str(#lit) }
+				}
+				AttrValue::Expr(expr) => {
+					quote! { ::brunhild::tokenizer::StrOrToken::from(&#expr) }
+				}
+			};
+			quote! { (#name, #value) }
+		});
+
+		// Build children in a vec! literal, unless there is control flow
+		let children = if self
+			.children
+			.iter()
+			.any(|ch| matches!(ch, Child::For { .. } | Child::If { .. }))
+		{
+			let statements = self.children.iter().map(Child::to_statements);
+			quote! {{
+				let mut __children = Vec::new();
+				#(#statements)*
+				__children
+			}}
+		} else {
+			let nodes = self.children.iter().map(Child::to_tokens);
+			quote! { vec![#(#nodes),*] }
+		};
+
+		quote! {
+			::brunhild::Node::with_children(
+				&::brunhild::ElementOptions {
+					tag: #tag,
+					key: #key,
+					attrs: &[#(#attrs),*],
+					..Default::default()
+				},
+				#children,
+			)
+		}
+	}
+}
+
+#[cfg(test)]
+fn parse_error(input: &str) -> String {
+	match syn::parse_str::<Root>(input) {
+		Ok(_) => panic!("parsed successfully: {}", input),
+		Err(e) => e.to_string(),
+	}
+}
+
+#[test]
+fn unclosed_tag() {
+	assert_eq!(parse_error("<div><span></span>"), "unclosed tag `<div>`");
+	assert_eq!(
+		parse_error("<div> for x in y { <span> } </div>"),
+		"unclosed tag `<span>`"
+	);
+	assert_eq!(parse_error("<div class=\"a\""), "unclosed tag `<div`");
+}
+
+#[test]
+fn mismatched_closing_tag() {
+	assert_eq!(
+		parse_error("<div></span>"),
+		"expected closing tag `</div>`, found `</span>`"
+	);
+}
+
+#[test]
+fn multiple_roots() {
+	assert_eq!(
+		parse_error("<div></div><div></div>"),
+		"html! must contain exactly one root node"
+	);
+}
+
+#[test]
+fn static_subtree() {
+	let tokens = |el: &str| match syn::parse_str::<Root>(el).unwrap().0 {
+		Child::Element(el) => el.to_tokens().to_string(),
+		_ => unreachable!(),
+	};
+	assert_eq!(
+		tokens(r#"<div class="a&b"><br/>"text"<p></p></div>"#),
+		concat!(
+			r#":: brunhild :: Node :: hoisted ("<div class=\"a&amp;b\">"#,
+			r#"<br><span>text</span><p></p></div>" , 4usize)"#,
+		)
+	);
+
+	// Attributes are sorted and deduplicated like at runtime. Hoisting
+	// depends on their names being sorted the same way by token.
+	let s = tokens(r#"<a title="t" class="b" hidden class="c"></a>"#);
+	assert!(s.contains(concat!(
+		r#"const HOISTED : bool = :: brunhild :: tokenizer :: "#,
+		r#"in_token_order (& ["class" , "hidden" , "title"])"#,
+	)));
+	assert!(s.contains(concat!(
+		r#"if HOISTED { :: brunhild :: Node :: hoisted "#,
+		r#"("<a class=\"c\" hidden title=\"t\"></a>" , 2usize) }"#,
+	)));
+	assert!(s.contains("else { :: brunhild :: Node :: with_children"));
+
+	// Only static children are hoisted
+	let s = tokens(r#"<div class={x}><p>"text"</p></div>"#);
+	assert!(s.starts_with(":: brunhild :: Node :: with_children"));
+	assert!(s.contains("hoisted (\"<p><span>text</span></p>\" , 2usize)"));
+}
+
+#[test]
+fn predefined_names() {
+	let tokens = |el: &str| match syn::parse_str::<Root>(el).unwrap().0 {
+		Child::Element(el) => el.to_tokens().to_string(),
+		_ => unreachable!(),
+	};
+	let s = tokens("<div class={1} data-id=\"x\"></div>");
+	assert!(s.contains("StrOrToken :: literal (\"div\")"));
+	assert!(s.contains("StrOrToken :: literal (\"class\")"));
+	assert!(s.contains("StrOrToken :: literal (\"data-id\")"));
+
+	let s = tokens("<my-element key={1}></my-element>");
+	assert!(s.contains("StrOrToken :: literal (\"my-element\")"));
+}
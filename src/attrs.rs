@@ -1,63 +1,97 @@
-use super::tokenizer;
+use super::error::Error;
+use super::tokenizer::{self, attr, StrOrToken, Token};
 use super::util;
 
-use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
-use wasm_bindgen::JsValue;
 
-// Attribute keys that have limited set of values and thus can have their
-// values tokenized.
+// Tokens of attribute keys that have limited set of values and thus can have
+// their values tokenized.
 // Sorted for binary search.
 //
 // Sourced from:
 // https://developer.mozilla.org/en-US/docs/Web/HTML/Attributes
-static TOKENIZABLE_VALUES: [&'static str; 35] = [
-	"async",
-	"autocapitalize",
-	"autocomplete",
-	"autofocus",
-	"autoplay",
-	"checked",
-	"class",
-	"contenteditable",
-	"controls",
-	"crossorigin",
-	"decoding",
-	"defer",
-	"dir",
-	"disabled",
-	"draggable",
-	"dropzone",
-	"hidden",
-	"language",
-	"loop",
-	"method",
-	"multiple",
-	"muted",
-	"novalidate",
-	"open",
-	"preload",
-	"readonly",
-	"referrerpolicy",
-	"required",
-	"reversed",
-	"sandbox",
-	"selected",
-	"spellcheck",
-	"translate",
-	"type",
-	"wrap",
+static TOKENIZABLE_VALUES: [Token; 35] = [
+	attr::ASYNC,
+	attr::AUTOCAPITALIZE,
+	attr::AUTOCOMPLETE,
+	attr::AUTOFOCUS,
+	attr::AUTOPLAY,
+	attr::CHECKED,
+	attr::CLASS,
+	attr::CONTENTEDITABLE,
+	attr::CONTROLS,
+	attr::CROSSORIGIN,
+	attr::DECODING,
+	attr::DEFER,
+	attr::DIR,
+	attr::DISABLED,
+	attr::DRAGGABLE,
+	attr::DROPZONE,
+	attr::HIDDEN,
+	attr::LANGUAGE,
+	attr::LOOP,
+	attr::METHOD,
+	attr::MULTIPLE,
+	attr::MUTED,
+	attr::NOVALIDATE,
+	attr::OPEN,
+	attr::PRELOAD,
+	attr::READONLY,
+	attr::REFERRERPOLICY,
+	attr::REQUIRED,
+	attr::REVERSED,
+	attr::SANDBOX,
+	attr::SELECTED,
+	attr::SPELLCHECK,
+	attr::TRANSLATE,
+	attr::TYPE,
+	attr::WRAP,
 ];
 
+thread_local! {
+	// Attribute keys registered with register_tokenizable_values(), that can
+	// have their values tokenized in addition to TOKENIZABLE_VALUES
+	static TOKENIZABLE_EXTRA: RefCell<HashSet<Token>> =
+		Default::default();
+}
+
+// Mark attribute keys as having a limited set of values, that should be
+// tokenized. The keys are also registered as predefined strings.
+//
+// Must be called at startup before any strings not in the predefined set have
+// been tokenized. See tokenizer::register_predefined().
+pub fn register_tokenizable_values(
+	keys: &[&str],
+) -> Result<(), tokenizer::RegistryError> {
+	tokenizer::register_predefined(keys)?;
+	util::with_global_mut(&TOKENIZABLE_EXTRA, |set| {
+		for k in keys {
+			set.insert(tokenizer::tokenize(k));
+		}
+	});
+	Ok(())
+}
+
+// Returns, if the values of the attribute key should be tokenized
+#[inline]
+fn is_tokenizable(key: &Token) -> bool {
+	TOKENIZABLE_VALUES.binary_search(key).is_ok()
+		|| util::with_global(&TOKENIZABLE_EXTRA, |set| {
+			!set.is_empty() && set.contains(key)
+		})
+}
+
 // Compressed attribute storage with manipulation functions
 #[derive(Default, Debug)]
-pub struct Attrs(BTreeMap<u16, Value>);
+pub struct Attrs(BTreeMap<Token, Value>);
 
 // Contains a value stored in one of 2 storage methods for attribute values
 #[derive(PartialEq, Eq, Debug)]
 enum Value {
 	// Tokenized string value
-	StringToken(u16),
+	StringToken(Token),
 
 	// Untokenized string. Used to store values too dynamic to benefit from
 	// tokenization in most use cases.
@@ -68,48 +102,61 @@ impl Attrs {
 	// Create empty attribute map
 	// TODO: Make generic with Into
 	#[inline]
-	pub fn new(arr: &[(&str, &str)]) -> Self {
+	pub fn new(arr: &[(StrOrToken, StrOrToken)]) -> Self {
 		Self(
 			arr.iter()
 				.map(|(key, val)| {
-					(
-						tokenizer::tokenize(key),
-						if *val == "" {
-							Value::StringToken(0)
-						} else {
-							match TOKENIZABLE_VALUES.binary_search(&key) {
-								Ok(_) => {
-									Value::StringToken(tokenizer::tokenize(val))
-								}
-								_ => Value::Untokenized(String::from(*val)),
+					let key = key.to_token();
+					let val = match val {
+						StrOrToken::Token(t) => Value::StringToken(t.clone()),
+						StrOrToken::Str("") => Value::StringToken(Token::EMPTY),
+						StrOrToken::Str(val) => {
+							let token = if is_tokenizable(&key) {
+								tokenizer::try_tokenize(val)
+							} else {
+								None
+							};
+							match token {
+								Some(t) => Value::StringToken(t),
+
+								// Also fall back to untokenized storage, if
+								// the token registry has been exhausted
+								None => Value::Untokenized(String::from(*val)),
 							}
-						},
-					)
+						}
+					};
+					(key, val)
 				})
 				.collect(),
 		)
 	}
 
+	// Call f with the key and value of each attribute
+	pub fn for_each<F: FnMut(&str, &str)>(&self, mut f: F) {
+		for (k, v) in self.0.iter() {
+			tokenizer::get_value(k, |k| match v {
+				Value::StringToken(v) => tokenizer::get_value(v, |v| f(k, v)),
+				Value::Untokenized(v) => f(k, v),
+			});
+		}
+	}
+
 	// Diff and patch attributes against new set and write changes to the DOM
 	pub fn patch(
 		&mut self,
 		el: &mut util::LazyElement,
 		new: Attrs,
-	) -> Result<(), JsValue> {
+	) -> Result<(), Error> {
 		// Attributes removed
-		let mut to_remove = Vec::<u16>::new();
+		let mut to_remove = Vec::<Token>::new();
 		for k in self.0.keys() {
 			if new.0.contains_key(k) {
 				continue;
 			}
 
-			to_remove.push(*k);
-			match el.get() {
-				Ok(el) => {
-					tokenizer::get_value(*k, |key| el.remove_attribute(key))
-				}
-				Err(e) => Err(e),
-			}?;
+			to_remove.push(k.clone());
+			let el = el.get()?;
+			tokenizer::get_value(k, |key| el.remove_attribute(key))?;
 		}
 		for k in to_remove {
 			self.0.remove(&k);
@@ -117,30 +164,27 @@ impl Attrs {
 
 		// Attributes added or changed
 		for (k, v) in new.0.into_iter() {
-			let mut set = |k: u16, v: &Value| -> Result<(), JsValue> {
-				match el.get() {
-					Ok(el) => tokenizer::get_value(k, |key| match v {
-						Value::StringToken(v) => {
-							tokenizer::get_value(*v, |value| {
-								el.set_attribute(key, value)
-							})
-						}
-						Value::Untokenized(value) => {
-							el.set_attribute(key, value)
-						}
+			let mut set = |k: &Token,
+			               v: &Value|
+			 -> Result<(), Error> {
+				let el = el.get()?;
+				tokenizer::get_value(k, |key| match v {
+					Value::StringToken(v) => tokenizer::get_value(v, |value| {
+						el.set_attribute(key, value)
 					}),
-					Err(e) => Err(e),
-				}
+					Value::Untokenized(value) => el.set_attribute(key, value),
+				})?;
+				Ok(())
 			};
 			match self.0.get_mut(&k) {
 				Some(old_v) => {
 					if v != *old_v {
-						set(k, &v)?;
+						set(&k, &v)?;
 						*old_v = v;
 					}
 				}
 				None => {
-					set(k, &v)?;
+					set(&k, &v)?;
 					self.0.insert(k, v);
 				}
 			}
@@ -152,19 +196,66 @@ impl Attrs {
 
 impl util::WriteHTMLTo for Attrs {
 	fn write_html_to<W: fmt::Write>(&mut self, w: &mut W) -> fmt::Result {
+		self.write_html_with(w, false)
+	}
+}
+
+impl Attrs {
+	// Write attributes as HTML to w with escaped values. If minify is set,
+	// quotes are omitted from values, that do not require them.
+	pub fn write_html_with<W: fmt::Write>(
+		&self,
+		w: &mut W,
+		minify: bool,
+	) -> fmt::Result {
 		for (k, v) in self.0.iter() {
-			tokenizer::get_value(*k, |s| write!(w, " {}", s))?;
+			tokenizer::get_value(k, |s| write!(w, " {}", s))?;
 			match v {
 				Value::StringToken(v) => {
-					if *v != 0 {
-						tokenizer::get_value(*v, |s| write!(w, "=\"{}\"", s))?;
+					if *v != Token::EMPTY {
+						tokenizer::get_value(v, |s| {
+							util::write_attr_value(w, s, minify)
+						})?;
 					}
 				}
-				Value::Untokenized(s) => {
-					write!(w, "=\"{}\"", s)?;
-				}
+				Value::Untokenized(s) => util::write_attr_value(w, s, minify)?,
 			};
 		}
 		Ok(())
 	}
 }
+
+#[test]
+fn tokenize_registered_keys() {
+	register_tokenizable_values(&["aria-expanded"]).unwrap();
+	let attrs = Attrs::new(&[
+		("aria-expanded".into(), "true".into()),
+		("data-state".into(), "open".into()),
+	]);
+	assert_eq!(
+		attrs.0.get(&tokenizer::tokenize("aria-expanded")),
+		Some(&Value::StringToken(tokenizer::tokenize("true")))
+	);
+	assert_eq!(
+		attrs.0.get(&tokenizer::tokenize("data-state")),
+		Some(&Value::Untokenized("open".into()))
+	);
+}
+
+#[test]
+fn pre_tokenized() {
+	let class = Token::from("pre-tokenized");
+	let attrs = Attrs::new(&[
+		(attr::CLASS.into(), (&class).into()),
+		// Tokens are stored as is, even for keys not marked tokenizable
+		("data-id".into(), class.clone().into()),
+	]);
+	assert_eq!(
+		attrs.0.get(&attr::CLASS),
+		Some(&Value::StringToken(class.clone()))
+	);
+	assert_eq!(
+		attrs.0.get(&Token::from("data-id")),
+		Some(&Value::StringToken(class))
+	);
+}
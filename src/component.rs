@@ -0,0 +1,407 @@
+use super::error::Error;
+use super::node::Node;
+use super::scheduler::{self, Priority};
+use std::any::{Any, TypeId};
+use std::cell::{RefCell, RefMut};
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+// Stateful view, that renders a Node subtree and owns it after mounting.
+//
+// Components are embedded into Node trees with Node::component() and act as
+// patch boundaries: changes to the local state of a component only re-render
+// and patch its own subtree.
+pub trait Component: Sized + 'static {
+	// Render the component's subtree from its current state
+	fn render(&self) -> Node;
+
+	// Called on an already rendered component with a newly constructed
+	// instance of the same type, when the parent Node is patched. Merge any
+	// properties passed by the parent into self and return, if the component
+	// should be re-rendered.
+	//
+	// Replaces self and always re-renders by default.
+	fn update(&mut self, new: Self) -> bool {
+		*self = new;
+		true
+	}
+
+	// Key used to identify the same component, during potentially destructive
+	// patching. See ElementOptions::key.
+	fn key(&self) -> Option<u64> {
+		None
+	}
+
+	// Called once before the first render with a handle for updating the
+	// component's local state
+	fn init(&mut self, _handle: Handle<Self>) {}
+}
+
+// Handle for updating the local state of a component. Does not keep the
+// component alive.
+pub struct Handle<C: Component>(Weak<RefCell<State<C>>>);
+
+impl<C: Component> Clone for Handle<C> {
+	fn clone(&self) -> Self {
+		Self(self.0.clone())
+	}
+}
+
+impl<C: Component> Handle<C> {
+	// Modify the component's local state with f. If f returns true, the
+	// component is re-rendered and the changes are patched into its subtree.
+	//
+	// Does nothing, if the component has already been dropped. Must not be
+	// called from inside Component::render() or Component::update().
+	pub fn set_state<F>(&self, f: F) -> Result<(), Error>
+	where
+		F: FnOnce(&mut C) -> bool,
+	{
+		match self.0.upgrade() {
+			Some(state) => {
+				let mut state = state.borrow_mut();
+				if f(&mut state.component) {
+					state.rerender()?;
+				}
+				Ok(())
+			}
+			None => Ok(()),
+		}
+	}
+
+	// Like set_state(), but the component is re-rendered and patched on the
+	// next animation frame. Any further state changes before then are
+	// coalesced into the same re-render. See scheduler.
+	pub fn schedule<F>(&self, priority: Priority, f: F) -> Result<(), Error>
+	where
+		F: FnOnce(&mut C) -> bool,
+	{
+		let state = match self.0.upgrade() {
+			Some(state) => state,
+			None => return Ok(()),
+		};
+		if !f(&mut state.borrow_mut().component) {
+			return Ok(());
+		}
+
+		let this = self.clone();
+		scheduler::enqueue(
+			Rc::as_ptr(&state) as usize,
+			priority,
+			Box::new(move || match this.0.upgrade() {
+				Some(state) => state.borrow_mut().rerender(),
+				None => Ok(()),
+			}),
+		)
+	}
+
+	// Returns, if the component has not been dropped yet
+	pub fn is_alive(&self) -> bool {
+		self.0.strong_count() != 0
+	}
+}
+
+// Component and its rendered subtree
+struct State<C: Component> {
+	this: Weak<RefCell<State<C>>>,
+	component: C,
+
+	// Rendered subtree. Not set until first render.
+	node: Option<Node>,
+}
+
+impl<C: Component> State<C> {
+	// Render component, initializing it on first render
+	fn render(&mut self) -> Node {
+		if self.node.is_none() {
+			self.component.init(Handle(self.this.clone()));
+		}
+		self.component.render()
+	}
+
+	// Render component and patch the new subtree into the old one, if any
+	fn rerender(&mut self) -> Result<(), Error> {
+		let new = self.render();
+		match &mut self.node {
+			Some(node) if node.is_mounted() => node.patch(new),
+			_ => {
+				self.node = Some(new);
+				Ok(())
+			}
+		}
+	}
+}
+
+// Type-erased component stored in a Node
+pub(crate) trait AnyComponent {
+	// Return type of the concrete component
+	fn component_type(&self) -> TypeId;
+
+	// Upcast for downcasting to the concrete type
+	fn into_any(self: Rc<Self>) -> Rc<dyn Any>;
+
+	// Return rendered subtree of component, rendering it, if needed
+	fn node(&self) -> RefMut<'_, Node>;
+
+	// Returns, if the component has been rendered and its subtree mounted.
+	// Does not render the component.
+	fn is_mounted(&self) -> bool;
+
+	// Update component with a newly constructed one of the same type and
+	// patch any changes into the rendered subtree
+	fn update(&self, new: Rc<dyn AnyComponent>) -> Result<(), Error>;
+}
+
+impl<C: Component> AnyComponent for RefCell<State<C>> {
+	fn component_type(&self) -> TypeId {
+		TypeId::of::<C>()
+	}
+
+	fn into_any(self: Rc<Self>) -> Rc<dyn Any> {
+		self
+	}
+
+	fn node(&self) -> RefMut<'_, Node> {
+		let mut state = self.borrow_mut();
+		if state.node.is_none() {
+			let node = state.render();
+			state.node = Some(node);
+		}
+		RefMut::map(state, |s| s.node.as_mut().unwrap())
+	}
+
+	fn is_mounted(&self) -> bool {
+		self.borrow().node.as_ref().is_some_and(Node::is_mounted)
+	}
+
+	fn update(&self, new: Rc<dyn AnyComponent>) -> Result<(), Error> {
+		let new = match new.into_any().downcast::<RefCell<State<C>>>() {
+			Ok(new) => new,
+			Err(_) => return Err(Error::Internal("component type mismatch")),
+		};
+		let new = match Rc::try_unwrap(new) {
+			Ok(new) => new.into_inner().component,
+			Err(_) => return Err(Error::Internal("component still referenced")),
+		};
+
+		let mut state = self.borrow_mut();
+		if state.component.update(new) {
+			state.rerender()?;
+		}
+		Ok(())
+	}
+}
+
+// Component stored in a Node
+pub(crate) struct ComponentNode(Rc<dyn AnyComponent>);
+
+impl ComponentNode {
+	pub fn new<C: Component>(c: C) -> Self {
+		Self(Rc::new_cyclic(|this| {
+			RefCell::new(State {
+				this: this.clone(),
+				component: c,
+				node: None,
+			})
+		}))
+	}
+
+	// Returns, if both nodes contain components of the same type
+	pub fn same_type(&self, other: &Self) -> bool {
+		self.0.component_type() == other.0.component_type()
+	}
+
+	// Return rendered subtree of component, rendering it, if needed
+	pub fn node(&self) -> RefMut<'_, Node> {
+		self.0.node()
+	}
+
+	// Returns, if the component has been rendered and its subtree mounted
+	pub fn is_mounted(&self) -> bool {
+		self.0.is_mounted()
+	}
+
+	// Update component with a newly constructed one of the same type and
+	// patch any changes into the rendered subtree
+	pub fn update(&self, new: ComponentNode) -> Result<(), Error> {
+		self.0.update(new.0)
+	}
+}
+
+impl fmt::Debug for ComponentNode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("ComponentNode")
+	}
+}
+
+#[cfg(test)]
+thread_local! {
+	static RENDERS: std::cell::Cell<usize> =
+		const { std::cell::Cell::new(0) };
+	static HANDLE: RefCell<Option<Handle<Counter>>> =
+		const { RefCell::new(None) };
+}
+
+// Counter with a label passed from the parent
+#[cfg(test)]
+struct Counter {
+	label: &'static str,
+	count: usize,
+}
+
+#[cfg(test)]
+impl Counter {
+	fn new(label: &'static str) -> Self {
+		Self { label, count: 0 }
+	}
+}
+
+#[cfg(test)]
+impl Component for Counter {
+	fn render(&self) -> Node {
+		RENDERS.with(|r| r.set(r.get() + 1));
+		crate::element!("span", { "class" => self.label }, [
+			crate::text!(self.count.to_string()),
+		])
+	}
+
+	fn update(&mut self, new: Self) -> bool {
+		let changed = self.label != new.label;
+		self.label = new.label;
+		changed
+	}
+
+	fn init(&mut self, handle: Handle<Self>) {
+		HANDLE.with(|h| {
+			assert!(h.borrow().is_none());
+			*h.borrow_mut() = Some(handle);
+		});
+	}
+}
+
+#[cfg(test)]
+fn renders() -> usize {
+	RENDERS.with(|r| r.get())
+}
+
+#[cfg(test)]
+fn handle() -> Handle<Counter> {
+	HANDLE.with(|h| h.borrow().clone().unwrap())
+}
+
+#[test]
+fn render_nested() -> Result<(), Error> {
+	let mut node = crate::element!("div", &[], vec![Node::component(
+		Counter::new("counter")
+	)]);
+	assert_eq!(renders(), 0);
+
+	let html = node.html()?;
+	assert_eq!(renders(), 1);
+	assert!(html.contains(r#" class="counter"><span id="bh-"#));
+	assert!(html.ends_with("\">0</span></span></div>"));
+
+	// Child element IDs are stable
+	assert_eq!(node.html()?, html);
+	assert_eq!(renders(), 1);
+	Ok(())
+}
+
+#[test]
+fn init_once() -> Result<(), Error> {
+	let mut node = Node::component(Counter::new("counter"));
+	node.html()?;
+	node.html()?;
+	assert_eq!(renders(), 1);
+	let h = handle();
+	assert!(h.is_alive());
+
+	// Unchanged output causes no DOM mutations
+	let (res, ops) = super::util::mock_dom(|| h.set_state(|_| true));
+	res?;
+	assert_eq!(renders(), 2);
+	assert_eq!(ops, []);
+	h.set_state(|_| false)?;
+	assert_eq!(renders(), 2);
+
+	drop(node);
+	assert!(!h.is_alive());
+	h.set_state(|_| unreachable!())?;
+	Ok(())
+}
+
+#[test]
+fn set_state_before_mount() -> Result<(), Error> {
+	let mut node = Node::component(Counter::new("counter"));
+
+	// Checking does not render the component
+	assert!(!node.is_mounted());
+	assert_eq!(renders(), 0);
+
+	// Rendered without IDs, which does not mount it
+	node.html_with(&crate::HtmlOptions {
+		ids: false,
+		..Default::default()
+	})?;
+	assert!(!node.is_mounted());
+	assert_eq!(renders(), 1);
+
+	// Not mounted yet, so the subtree is just replaced
+	handle().set_state(|c| {
+		c.count = 2;
+		true
+	})?;
+	assert_eq!(renders(), 2);
+	assert!(node.html()?.ends_with("\">2</span></span>"));
+	Ok(())
+}
+
+#[test]
+fn patch_boundary() -> Result<(), Error> {
+	let mut node = Node::component(Counter::new("counter"));
+	node.html()?;
+	let id = node.element_id();
+	handle().set_state(|c| {
+		c.count = 3;
+		false
+	})?;
+	assert_eq!(renders(), 1);
+
+	// Parent re-render does not reset local state or re-render the
+	// component, if update() returns false
+	let (res, ops) = super::util::mock_dom(|| {
+		node.patch(Node::component(Counter::new("counter")))
+	});
+	res?;
+	assert_eq!(ops, []);
+	assert_eq!(renders(), 1);
+	assert_eq!(node.element_id(), id);
+	assert!(node.html()?.ends_with("\">0</span></span>"));
+
+	handle().set_state(|c| {
+		c.count = 0;
+		true
+	})?;
+	assert_eq!(renders(), 2);
+	Ok(())
+}
+
+#[test]
+fn scheduled_state() -> Result<(), Error> {
+	let clock = scheduler::ManualClock::default();
+	scheduler::set_clock(clock.clone());
+	let mut node = Node::component(Counter::new("counter"));
+	node.html()?;
+
+	for _ in 0..3 {
+		handle().schedule(Priority::Normal, |_| true)?;
+	}
+	handle().schedule(Priority::Normal, |_| false)?;
+	assert_eq!(renders(), 1);
+	assert_eq!(clock.requested(), 1);
+
+	// Coalesced into a single re-render
+	clock.tick();
+	assert_eq!(renders(), 2);
+	Ok(())
+}
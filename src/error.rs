@@ -0,0 +1,83 @@
+use std::fmt;
+use wasm_bindgen::JsValue;
+
+// Error returned by fallible brunhild operations.
+//
+// Converts into JsValue for throwing as a JS exception.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Error {
+	// Node must be mounted before patching
+	NotMounted,
+
+	// DOM element with the ID of a mounted node was not found. The element was
+	// most likely removed from the DOM by something other than brunhild.
+	ElementNotFound { id: u64 },
+
+	// Node tree was modified by other means during an incremental patch
+	TreeChanged,
+
+	// Exception thrown by a JS API
+	Js(JsValue),
+
+	// Formatting HTML failed
+	Format,
+
+	// Violated internal invariant. Indicates a bug in brunhild.
+	Internal(&'static str),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::NotMounted => f.write_str("node not mounted yet"),
+			Error::ElementNotFound { id } => {
+				write!(f, "element not found: bh-{}", id)
+			}
+			Error::TreeChanged => {
+				f.write_str("node tree changed during patching")
+			}
+			Error::Js(v) => match v.as_string() {
+				Some(s) => write!(f, "JS exception: {}", s),
+				None => write!(f, "JS exception: {:?}", v),
+			},
+			Error::Format => f.write_str("failed to format HTML"),
+			Error::Internal(s) => write!(f, "internal error: {}", s),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+impl From<JsValue> for Error {
+	fn from(v: JsValue) -> Self {
+		Error::Js(v)
+	}
+}
+
+impl From<fmt::Error> for Error {
+	fn from(_: fmt::Error) -> Self {
+		Error::Format
+	}
+}
+
+impl From<Error> for JsValue {
+	// Thrown JS exceptions are passed through as is. Other errors are
+	// converted to JS Error objects.
+	fn from(e: Error) -> Self {
+		match e {
+			Error::Js(v) => v,
+			e => js_sys::Error::new(&e.to_string()).into(),
+		}
+	}
+}
+
+#[test]
+fn display() {
+	assert_eq!(Error::NotMounted.to_string(), "node not mounted yet");
+	assert_eq!(
+		Error::ElementNotFound { id: 3 }.to_string(),
+		"element not found: bh-3"
+	);
+	assert_eq!(Error::from(fmt::Error).to_string(), "failed to format HTML");
+}
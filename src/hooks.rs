@@ -0,0 +1,74 @@
+use std::fmt;
+use std::rc::Rc;
+
+// Callback invoked with the DOM element of a Node
+pub type Hook = Rc<dyn Fn(&web_sys::Element)>;
+
+// Lifecycle callbacks of an element Node. Each is invoked after the
+// corresponding DOM operation has completed.
+#[derive(Default, Clone)]
+pub struct Hooks {
+	// Called after the element has been inserted into the DOM
+	pub on_mount: Option<Hook>,
+
+	// Called after the element has been patched in place
+	pub on_update: Option<Hook>,
+
+	// Called after the element has been removed from the DOM. Also called for
+	// every descendant element of a removed element.
+	pub on_unmount: Option<Hook>,
+}
+
+impl Hooks {
+	// Returns, if no hooks are set
+	pub fn is_empty(&self) -> bool {
+		self.on_mount.is_none()
+			&& self.on_update.is_none()
+			&& self.on_unmount.is_none()
+	}
+}
+
+impl fmt::Debug for Hooks {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Hooks")
+			.field("on_mount", &self.on_mount.is_some())
+			.field("on_update", &self.on_update.is_some())
+			.field("on_unmount", &self.on_unmount.is_some())
+			.finish()
+	}
+}
+
+// Selects which hook to collect
+#[derive(Clone, Copy)]
+pub enum Lifecycle {
+	Mount,
+	Unmount,
+}
+
+impl Lifecycle {
+	// Return hook for this lifecycle event, if set
+	pub fn select(self, hooks: &Hooks) -> Option<&Hook> {
+		match self {
+			Lifecycle::Mount => hooks.on_mount.as_ref(),
+			Lifecycle::Unmount => hooks.on_unmount.as_ref(),
+		}
+	}
+}
+
+// Hooks with their resolved elements collected for calling after a DOM
+// operation
+#[derive(Default)]
+pub struct Pending(Vec<(web_sys::Element, Hook)>);
+
+impl Pending {
+	pub fn push(&mut self, el: web_sys::Element, hook: Hook) {
+		self.0.push((el, hook));
+	}
+
+	// Call all collected hooks in collection order
+	pub fn call(self) {
+		for (el, hook) in self.0 {
+			hook(&el);
+		}
+	}
+}
@@ -0,0 +1,188 @@
+use super::error::Error;
+use super::node::Node;
+use super::scheduler::{self, Priority};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Limits on the amount of work done by one IncrementalPatch::step()
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Budget {
+	// Maximum number of nodes to patch. Destructive replacements, keyed child
+	// list reordering, components and memoized subtrees each count as one
+	// node.
+	pub nodes: Option<usize>,
+
+	// Maximum time to spend in milliseconds, as measured by the scheduler
+	// clock. Checked after each node.
+	pub time: Option<f64>,
+}
+
+// Interruptible patch of a root Node, that walks the diff with an explicit
+// work stack and yields, once its budget is exhausted.
+//
+// Every node is patched in full before yielding, so the virtual tree matches
+// the DOM at every yield point. Element lifecycle on_update hooks are called
+// before the element's children are patched.
+//
+// The root must not be patched by other means before the IncrementalPatch is
+// done.
+pub struct IncrementalPatch {
+	root: Rc<RefCell<Node>>,
+
+	// Pending patches as paths of child indexes from the root and the new node
+	// to patch in
+	stack: Vec<(Vec<usize>, Node)>,
+}
+
+impl IncrementalPatch {
+	// Create patch of root with new. Root must be already mounted.
+	pub fn new(root: Rc<RefCell<Node>>, new: Node) -> Result<Self, Error> {
+		if !root.borrow().is_mounted() {
+			return Err(Error::NotMounted);
+		}
+		Ok(Self {
+			root,
+			stack: vec![(Vec::new(), new)],
+		})
+	}
+
+	// Returns, if all changes have been applied
+	pub fn is_done(&self) -> bool {
+		self.stack.is_empty()
+	}
+
+	// Apply changes until done or budget is exhausted. Returns, if done.
+	pub fn step(&mut self, budget: Budget) -> Result<bool, Error> {
+		let start = budget.time.map(|_| scheduler::now());
+		let mut patched = 0;
+		let mut children = Vec::new();
+
+		while let Some((path, new)) = self.stack.pop() {
+			let mut root = self.root.borrow_mut();
+			let mut node = &mut *root;
+			for i in path.iter() {
+				node = match node.child_mut(*i) {
+					Some(ch) => ch,
+					None => {
+						return Err(Error::TreeChanged)
+					}
+				};
+			}
+			node.patch_shallow(new, &mut children)?;
+			for (i, ch) in children.drain(..) {
+				let mut path = path.clone();
+				path.push(i);
+				self.stack.push((path, ch));
+			}
+
+			patched += 1;
+			if budget.nodes.map(|max| patched >= max).unwrap_or(false)
+				|| match (start, budget.time) {
+					(Some(start), Some(max)) => scheduler::now() - start >= max,
+					_ => false,
+				} {
+				break;
+			}
+		}
+
+		Ok(self.is_done())
+	}
+
+	// Apply all remaining changes immediately
+	pub fn finish(&mut self) -> Result<(), Error> {
+		self.step(Budget::default()).map(|_| ())
+	}
+
+	// Apply changes over consecutive frames with budget per frame. Replaces
+	// any patch of the same root scheduled before.
+	pub fn schedule(
+		mut self,
+		priority: Priority,
+		budget: Budget,
+	) -> Result<(), Error> {
+		scheduler::enqueue(
+			Rc::as_ptr(&self.root) as usize,
+			priority,
+			Box::new(move || {
+				if self.step(budget)? {
+					Ok(())
+				} else {
+					self.schedule(priority, budget)
+				}
+			}),
+		)
+	}
+}
+
+#[cfg(test)]
+use super::{ElementOptions, TextOptions};
+
+// Tree of width^depth leaf text nodes
+#[cfg(test)]
+fn tree(depth: usize, width: usize) -> Node {
+	if depth == 0 {
+		return Node::text(&TextOptions {
+			text: "leaf",
+			..Default::default()
+		});
+	}
+	Node::with_children(
+		&ElementOptions::default(),
+		(0..width).map(|_| tree(depth - 1, width)).collect(),
+	)
+}
+
+#[cfg(test)]
+fn mounted_tree(
+	depth: usize,
+	width: usize,
+) -> Result<Rc<RefCell<Node>>, Error> {
+	let mut node = tree(depth, width);
+	node.html()?;
+	Ok(Rc::new(RefCell::new(node)))
+}
+
+#[test]
+fn node_budget() -> Result<(), Error> {
+	// 1 + 2 + 4 + 8 nodes
+	let root = mounted_tree(3, 2)?;
+	let mut p = IncrementalPatch::new(root, tree(3, 2))?;
+
+	let budget = Budget {
+		nodes: Some(4),
+		..Default::default()
+	};
+	let mut steps = 0;
+	while !p.step(budget)? {
+		steps += 1;
+	}
+	assert_eq!(steps, 3);
+	assert!(p.is_done());
+	Ok(())
+}
+
+#[test]
+fn time_budget() -> Result<(), Error> {
+	let clock = scheduler::ManualClock::default();
+	scheduler::set_clock(clock.clone());
+	let root = mounted_tree(2, 3)?;
+	let html = root.borrow_mut().html()?;
+
+	IncrementalPatch::new(root.clone(), tree(2, 3))?.schedule(
+		Priority::Normal,
+		Budget {
+			time: Some(0.0),
+			..Default::default()
+		},
+	)?;
+
+	// Zero time budget patches one node per frame
+	let mut frames = 0;
+	while clock.requested() != 0 {
+		clock.tick();
+		frames += 1;
+	}
+	assert_eq!(frames, 1 + 3 + 9);
+	assert_eq!(root.borrow_mut().html()?, html);
+	Ok(())
+}
@@ -1,6 +1,30 @@
+// Allows html! expansions to refer to the crate as ::brunhild internally
+extern crate self as brunhild;
+
 mod attrs;
+mod component;
+mod error;
+mod hooks;
+mod incremental;
 mod node;
-mod tokenizer;
+mod node_ref;
+mod parse;
+mod preserve;
+mod render;
+pub mod scheduler;
+pub mod tokenizer;
 mod util;
 
-pub use node::{ElementOptions, Node, TextOptions};
+pub use brunhild_macros::html;
+pub use component::{Component, Handle};
+pub use error::Error;
+pub use hooks::{Hook, Hooks};
+pub use incremental::{Budget, IncrementalPatch};
+pub use node::{Divergence, ElementOptions, HtmlOptions, Node, TextOptions};
+pub use node_ref::NodeRef;
+pub use parse::ParseError;
+pub use render::HtmlChunks;
+
+// Exposed for benchmarks
+#[doc(hidden)]
+pub use util::Appender;
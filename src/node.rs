@@ -1,10 +1,17 @@
 use super::attrs::Attrs;
-use super::tokenizer;
+use super::component::{Component, ComponentNode};
+use super::error::Error;
+use super::hooks::{self, Hooks, Lifecycle};
+use super::node_ref::NodeRef;
+use super::preserve::Preserved;
+use super::tokenizer::{self, StrOrToken};
 use super::util;
 use super::util::WriteHTMLTo;
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fmt;
-use wasm_bindgen::JsValue;
+use std::hash::{Hash, Hasher};
 
 // Creates a new element node
 #[macro_export]
@@ -33,14 +40,17 @@ macro_rules! element {
 	($tag:expr, {$($key:expr => $val:expr,)+}, $children:expr) => {
 		$crate::element!{
 			$tag,
-			&[$(($key.as_ref(), $val.as_ref()),)+],
+			&[$((
+				$crate::tokenizer::StrOrToken::from(&$key),
+				$crate::tokenizer::StrOrToken::from(&$val),
+			),)+],
 			$children
 		}
 	};
 	($tag:expr, $attrs:expr, $children:expr) => {
 		$crate::Node::with_children(
 			&$crate::ElementOptions {
-				tag: $tag.as_ref(),
+				tag: $crate::tokenizer::StrOrToken::from(&$tag),
 				attrs: $attrs,
 				..Default::default()
 			},
@@ -53,7 +63,7 @@ macro_rules! element {
 #[macro_export]
 macro_rules! text {
 	($text:expr) => {
-		$crate::Node::text(&TextOptions {
+		$crate::Node::text(&$crate::TextOptions {
 			text: $text.as_ref(),
 			..Default::default()
 			})
@@ -64,7 +74,7 @@ macro_rules! text {
 #[macro_export]
 macro_rules! escaped {
 	($text:expr) => {
-		$crate::Node::text(&TextOptions {
+		$crate::Node::text(&$crate::TextOptions {
 			text: $text.as_ref(),
 			escape: true,
 			..Default::default()
@@ -72,11 +82,15 @@ macro_rules! escaped {
 	};
 }
 
-// Internal contents of a text Node or Element
+// Internal contents of a text Node, Element, Component, memoized or static
+// subtree
 #[derive(Debug)]
 enum NodeContents {
-	Text(String),
+	Text(TextContents),
 	Element(ElementContents),
+	Component(ComponentNode),
+	Memo(MemoContents),
+	Static(StaticContents),
 }
 
 impl Default for NodeContents {
@@ -85,11 +99,22 @@ impl Default for NodeContents {
 	}
 }
 
+// Internal contents of a text Node
+#[derive(Debug)]
+struct TextContents {
+	// Text as written to HTML. Already escaped, if escape was set.
+	text: String,
+
+	// Text was HTML-escaped on creation and can be compared to the text
+	// content of its element
+	escaped: bool,
+}
+
 // Internal contents of an Element
 #[derive(Debug)]
 struct ElementContents {
 	// Token for the node's tag
-	tag: u16,
+	tag: tokenizer::Token,
 
 	// Node attributes, excluding "id" and "class".
 	// "id" is used internally for node addressing and can not be set.
@@ -98,15 +123,84 @@ struct ElementContents {
 
 	// Children of Node
 	children: Vec<Node>,
+
+	// Lifecycle hooks of the element, if any are set
+	hooks: Option<Box<Hooks>>,
+
+	// Handle to fill with the DOM element
+	node_ref: Option<NodeRef>,
+
+	// Element or any of its descendants might have hooks or a NodeRef set.
+	// Used to skip traversal of subtrees without any hooks.
+	subtree_hooks: bool,
+
+	// Preserve scroll offsets across destructive replacement of an ancestor
+	preserve_scroll: bool,
+}
+
+impl ElementContents {
+	// Recompute subtree_hooks after a change to hooks or children
+	fn update_subtree_hooks(&mut self) {
+		self.subtree_hooks = self.hooks.is_some()
+			|| self.node_ref.is_some()
+			|| self.children.iter().any(|ch| ch.may_have_hooks());
+	}
 }
 
 impl Default for ElementContents {
 	fn default() -> Self {
 		Self {
-			tag: tokenizer::tokenize("div"),
+			tag: tokenizer::tag::DIV,
 			attrs: Default::default(),
 			children: Default::default(),
+			hooks: None,
+			node_ref: None,
+			subtree_hooks: false,
+			preserve_scroll: false,
+		}
+	}
+}
+
+// Internal contents of a memoized subtree
+struct MemoContents {
+	// Dependencies the subtree was built from
+	deps: Box<dyn Any>,
+
+	// Compares deps of the same type. Deps of different types are unequal.
+	deps_eq: fn(&dyn Any, &dyn Any) -> bool,
+
+	// Builds the subtree. Consumed on first use.
+	build: Option<Box<dyn FnOnce() -> Node>>,
+
+	// Built subtree
+	node: Option<Box<Node>>,
+}
+
+impl MemoContents {
+	// Return subtree, building it, if needed
+	fn node(&mut self) -> &mut Node {
+		if let Some(build) = self.build.take() {
+			self.node = Some(Box::new(build()));
 		}
+		self.node.as_mut().unwrap()
+	}
+}
+
+// Internal contents of a static subtree prerendered to HTML
+#[derive(Debug)]
+struct StaticContents {
+	// HTML of the subtree without the root element ID
+	html: &'static str,
+
+	// Position in html to insert the root element ID attribute at
+	id_at: usize,
+}
+
+impl fmt::Debug for MemoContents {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("MemoContents")
+			.field("node", &self.node)
+			.finish()
 	}
 }
 
@@ -132,10 +226,13 @@ pub struct Node {
 // Options for constructing an Element Node. This struct has separate lifetimes
 // for each field, so that some of these can have static lifetimes and thus not
 // require runtime allocation.
+//
+// Strings can be passed as pre-interned tokenizer::Token values to skip
+// tokenization.
 #[derive(Debug)]
 pub struct ElementOptions<'t, 'a> {
 	// Element HTML tag
-	pub tag: &'t str,
+	pub tag: StrOrToken<'t>,
 
 	// Kee used to identify the same node, during potentially destructive
 	// patching. Only set, if this node requires persistance, like maintaining
@@ -143,15 +240,29 @@ pub struct ElementOptions<'t, 'a> {
 	pub key: Option<u64>,
 
 	// List of element attributes
-	pub attrs: &'a [(&'a str, &'a str)],
+	pub attrs: &'a [(StrOrToken<'a>, StrOrToken<'a>)],
+
+	// Lifecycle hooks of the element
+	pub hooks: Hooks,
+
+	// Handle to fill with the DOM element of the Node, once mounted
+	pub node_ref: Option<NodeRef>,
+
+	// Preserve scroll offsets of a keyed element across destructive
+	// replacement of an ancestor. Scroll offsets are always preserved for the
+	// focused element and its keyed ancestors.
+	pub preserve_scroll: bool,
 }
 
 impl<'t, 'a> Default for ElementOptions<'t, 'a> {
 	fn default() -> Self {
 		Self {
-			tag: "div",
+			tag: StrOrToken::Token(tokenizer::tag::DIV),
 			key: None,
 			attrs: &[],
+			hooks: Default::default(),
+			node_ref: None,
+			preserve_scroll: false,
 		}
 	}
 }
@@ -171,6 +282,158 @@ pub struct TextOptions<'a> {
 	pub key: Option<u64>,
 }
 
+// Options for rendering Nodes to HTML with Node::html_with()
+#[derive(Clone, Debug)]
+pub struct HtmlOptions<'a> {
+	// Include element IDs used by brunhild to locate elements in the DOM.
+	// Disable for static export of HTML, that will never be patched.
+	//
+	// Defaults to true.
+	pub ids: bool,
+
+	// Pretty-print the HTML with each node on its own line, indented by this
+	// string per nesting level. Static subtrees are written as is.
+	pub indent: Option<&'a str>,
+
+	// Omit optional closing tags and attribute value quotes, where the HTML
+	// specification allows it. Static subtrees are written as is.
+	pub minify: bool,
+}
+
+impl<'a> Default for HtmlOptions<'a> {
+	fn default() -> Self {
+		Self {
+			ids: true,
+			indent: None,
+			minify: false,
+		}
+	}
+}
+
+// Difference between the DOM and the virtual tree found by Node::verify().
+// Elements are identified by the numeric part of their bh-N ID.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Divergence {
+	// Element of a node was not found in the document
+	Missing { id: u64 },
+
+	// Element has a different tag. Its subtree is not checked further.
+	Tag {
+		id: u64,
+		expected: String,
+		found: String,
+	},
+
+	// Attribute has a different value or is only set on one side
+	Attribute {
+		id: u64,
+		name: String,
+		expected: Option<String>,
+		found: Option<String>,
+	},
+
+	// Text node has different text content
+	Text {
+		id: u64,
+		expected: String,
+		found: String,
+	},
+
+	// Element has a different number of child elements
+	Children {
+		id: u64,
+		expected: usize,
+		found: usize,
+	},
+}
+
+impl fmt::Display for Divergence {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Divergence::Missing { id } => write!(f, "bh-{}: missing", id),
+			Divergence::Tag {
+				id,
+				expected,
+				found,
+			} => write!(
+				f,
+				"bh-{}: expected <{}>, found <{}>",
+				id, expected, found
+			),
+			Divergence::Attribute {
+				id,
+				name,
+				expected,
+				found,
+			} => write!(
+				f,
+				"bh-{}: expected {}={:?}, found {:?}",
+				id, name, expected, found
+			),
+			Divergence::Text {
+				id,
+				expected,
+				found,
+			} => write!(
+				f,
+				"bh-{}: expected text {:?}, found {:?}",
+				id, expected, found
+			),
+			Divergence::Children {
+				id,
+				expected,
+				found,
+			} => write!(
+				f,
+				"bh-{}: expected {} children, found {}",
+				id, expected, found
+			),
+		}
+	}
+}
+
+// DOM element as read by Node::verify(). Separates the comparison from the
+// DOM bindings.
+trait VerifiedElement {
+	// Lowercase tag name
+	fn tag(&self) -> String;
+
+	// Text content of the element and its descendants
+	fn text(&self) -> String;
+
+	fn attribute(&self, name: &str) -> Option<String>;
+
+	fn attribute_names(&self) -> Vec<String>;
+
+	// Number of child elements
+	fn child_count(&self) -> usize;
+}
+
+impl VerifiedElement for web_sys::Element {
+	fn tag(&self) -> String {
+		self.tag_name().to_ascii_lowercase()
+	}
+
+	fn text(&self) -> String {
+		self.text_content().unwrap_or_default()
+	}
+
+	fn attribute(&self, name: &str) -> Option<String> {
+		self.get_attribute(name)
+	}
+
+	fn attribute_names(&self) -> Vec<String> {
+		self.get_attribute_names()
+			.iter()
+			.filter_map(|name| name.as_string())
+			.collect()
+	}
+
+	fn child_count(&self) -> usize {
+		self.child_element_count() as usize
+	}
+}
+
 impl<'a> Default for TextOptions<'a> {
 	fn default() -> Self {
 		Self {
@@ -191,25 +454,111 @@ impl Node {
 	// Create an Element Node with children
 	#[inline]
 	pub fn with_children(opts: &ElementOptions, children: Vec<Node>) -> Self {
+		let mut cont = ElementContents {
+			tag: opts.tag.to_token(),
+			attrs: super::attrs::Attrs::new(opts.attrs),
+			children,
+			hooks: if opts.hooks.is_empty() {
+				None
+			} else {
+				Some(Box::new(opts.hooks.clone()))
+			},
+			node_ref: opts.node_ref.clone(),
+			subtree_hooks: false,
+			preserve_scroll: opts.preserve_scroll,
+		};
+		cont.update_subtree_hooks();
 		Self {
-			contents: NodeContents::Element(ElementContents {
-				tag: tokenizer::tokenize(opts.tag),
-				attrs: super::attrs::Attrs::new(opts.attrs),
-				children: children,
-			}),
+			contents: NodeContents::Element(cont),
 			key: opts.key,
 			..Default::default()
 		}
 	}
 
+	// Create a Node, that renders a Component. The component's subtree is
+	// rendered on mount and patched independently from its parent on
+	// component state changes.
+	#[inline]
+	pub fn component<C: Component>(c: C) -> Self {
+		Self {
+			key: c.key(),
+			contents: NodeContents::Component(ComponentNode::new(c)),
+			..Default::default()
+		}
+	}
+
+	// Create a Node, that memoizes the subtree returned by build. build is only
+	// called, if the subtree is mounted or deps differ from the deps of the
+	// memoized Node being patched. Otherwise, both construction and diffing of
+	// the subtree are skipped.
+	#[inline]
+	pub fn memo<D, F>(deps: D, build: F) -> Self
+	where
+		D: PartialEq + 'static,
+		F: FnOnce() -> Node + 'static,
+	{
+		fn deps_eq<D: PartialEq + 'static>(a: &dyn Any, b: &dyn Any) -> bool {
+			match (a.downcast_ref::<D>(), b.downcast_ref::<D>()) {
+				(Some(a), Some(b)) => a == b,
+				_ => false,
+			}
+		}
+
+		Self {
+			contents: NodeContents::Memo(MemoContents {
+				deps: Box::new(deps),
+				deps_eq: deps_eq::<D>,
+				build: Some(Box::new(build)),
+				node: None,
+			}),
+			..Default::default()
+		}
+	}
+
+	// Like memo(), but only stores and compares a 64 bit hash of deps. Avoids
+	// storing deps, that are expensive to clone or borrow from the caller.
+	//
+	// A hash collision between old and new deps skips a needed update of the
+	// subtree. Prefer memo(), unless this is acceptable.
+	#[inline]
+	pub fn memo_hashed<D, F>(deps: &D, build: F) -> Self
+	where
+		D: Hash + ?Sized,
+		F: FnOnce() -> Node + 'static,
+	{
+		#[derive(PartialEq)]
+		struct Hashed(u64);
+
+		let mut h = DefaultHasher::new();
+		deps.hash(&mut h);
+		Node::memo(Hashed(h.finish()), build)
+	}
+
+	// Create a Node from a static subtree prerendered to HTML. id_at is the
+	// byte position in html right after the root element's tag name.
+	//
+	// Used by html! for subtrees without any interpolations. Static Nodes are
+	// never diffed.
+	#[doc(hidden)]
+	#[inline]
+	pub fn hoisted(html: &'static str, id_at: usize) -> Self {
+		Self {
+			contents: NodeContents::Static(StaticContents { html, id_at }),
+			..Default::default()
+		}
+	}
+
 	// Create a text Node with set inner content
 	#[inline]
 	pub fn text(opts: &TextOptions) -> Self {
 		Self {
-			contents: NodeContents::Text(if opts.escape {
-				util::html_escape(opts.text.into())
-			} else {
-				opts.text.into()
+			contents: NodeContents::Text(TextContents {
+				text: if opts.escape {
+					util::html_escape(opts.text)
+				} else {
+					opts.text.into()
+				},
+				escaped: opts.escape,
 			}),
 			key: opts.key,
 			..Default::default()
@@ -217,16 +566,16 @@ impl Node {
 	}
 
 	// Mount Node as passed Element. Sets the element's ID attribute.
-	pub fn mount_as(&mut self, el: &web_sys::Element) -> Result<(), JsValue> {
+	pub fn mount_as(&mut self, el: &web_sys::Element) -> Result<(), Error> {
 		el.set_outer_html(&self.html()?);
-		Ok(())
+		self.call_hooks(Lifecycle::Mount)
 	}
 
 	// Mount Node as last child of parent
 	pub fn mount_append_to(
 		&mut self,
 		parent: &web_sys::Element,
-	) -> Result<(), JsValue> {
+	) -> Result<(), Error> {
 		self.mount(parent, "beforeend")
 	}
 
@@ -234,7 +583,7 @@ impl Node {
 	pub fn mount_prepend_to(
 		&mut self,
 		parent: &web_sys::Element,
-	) -> Result<(), JsValue> {
+	) -> Result<(), Error> {
 		self.mount(parent, "afterbegin")
 	}
 
@@ -242,7 +591,7 @@ impl Node {
 	pub fn mount_before(
 		&mut self,
 		parent: &web_sys::Element,
-	) -> Result<(), JsValue> {
+	) -> Result<(), Error> {
 		self.mount(parent, "beforebegin")
 	}
 
@@ -250,7 +599,7 @@ impl Node {
 	pub fn mount_after(
 		&mut self,
 		parent: &web_sys::Element,
-	) -> Result<(), JsValue> {
+	) -> Result<(), Error> {
 		self.mount(parent, "afterend")
 	}
 
@@ -258,34 +607,157 @@ impl Node {
 		&mut self,
 		parent: &web_sys::Element,
 		mode: &str,
-	) -> Result<(), JsValue> {
-		parent.insert_adjacent_html(mode, &self.html()?)
+	) -> Result<(), Error> {
+		parent.insert_adjacent_html(mode, &self.html()?)?;
+		self.call_hooks(Lifecycle::Mount)
 	}
 
 	// Return the DOM element ID of node
 	pub fn element_id(&self) -> String {
-		format!("bh-{}", self.id)
+		match &self.contents {
+			NodeContents::Component(c) => c.node().element_id(),
+			NodeContents::Memo(MemoContents {
+				node: Some(node), ..
+			}) => node.element_id(),
+			_ => format!("bh-{}", self.id),
+		}
+	}
+
+	// Returns, if the Node has already been formatted as HTML for mounting
+	pub(crate) fn is_mounted(&self) -> bool {
+		match &self.contents {
+			NodeContents::Component(c) => c.is_mounted(),
+			NodeContents::Memo(m) => {
+				m.node.as_ref().map(|n| n.is_mounted()).unwrap_or(false)
+			}
+			_ => self.id != 0,
+		}
+	}
+
+	// Returns, if the Node or any of its descendants might have hooks set
+	fn may_have_hooks(&self) -> bool {
+		match &self.contents {
+			NodeContents::Text(_) | NodeContents::Static(_) => false,
+			NodeContents::Element(cont) => cont.subtree_hooks,
+			NodeContents::Component(_) | NodeContents::Memo(_) => true,
+		}
+	}
+
+	// Collect hooks of the subtree for the lifecycle event with their
+	// resolved elements. Descendants are collected before their ancestors.
+	//
+	// Also fills any NodeRef in the subtree on mount and clears it on unmount.
+	fn collect_hooks(
+		&mut self,
+		event: Lifecycle,
+		dst: &mut hooks::Pending,
+	) -> Result<(), Error> {
+		if !self.may_have_hooks() {
+			return Ok(());
+		}
+		match &mut self.contents {
+			NodeContents::Text(_) | NodeContents::Static(_) => (),
+			NodeContents::Element(cont) => {
+				for ch in cont.children.iter_mut() {
+					ch.collect_hooks(event, dst)?;
+				}
+				if let Some(r) = &cont.node_ref {
+					match event {
+						Lifecycle::Mount => r.set(self.id, self.element.get()?),
+						Lifecycle::Unmount => r.clear(self.id),
+					}
+				}
+				if let Some(h) =
+					cont.hooks.as_ref().and_then(|h| event.select(h))
+				{
+					dst.push(self.element.get()?, h.clone());
+				}
+			}
+			NodeContents::Component(c) => c.node().collect_hooks(event, dst)?,
+			NodeContents::Memo(m) => {
+				if let Some(node) = &mut m.node {
+					node.collect_hooks(event, dst)?;
+				}
+			}
+		};
+		Ok(())
+	}
+
+	// Call hooks of the subtree for the lifecycle event
+	fn call_hooks(&mut self, event: Lifecycle) -> Result<(), Error> {
+		let mut pending = hooks::Pending::default();
+		self.collect_hooks(event, &mut pending)?;
+		pending.call();
+		Ok(())
+	}
+
+	// Return the lazily retrieved DOM element of the node. Caches the element
+	// on the node.
+	fn dom_element(&mut self) -> Result<util::LazyElement, Error> {
+		match &mut self.contents {
+			NodeContents::Component(c) => c.node().dom_element(),
+			NodeContents::Memo(m) => m.node().dom_element(),
+			_ => {
+				self.element.get()?;
+				Ok(self.element.clone())
+			}
+		}
 	}
 
 	// Patch possibly changed subtree into self and apply changes to the DOM.
 	// Node must be already mounted.
-	pub fn patch(&mut self, new: Node) -> Result<(), JsValue> {
-		if self.id == 0 {
-			return Err("node not mounted yet".into());
+	pub fn patch(&mut self, new: Node) -> Result<(), Error> {
+		if !self.is_mounted() {
+			return Err(Error::NotMounted);
+		}
+		self.patch_node(new, None)
+	}
+
+	// Patch self without descending into the children of matching elements.
+	// Pending child patches are pushed to dst as pairs of child index and new
+	// child in reverse order.
+	pub(crate) fn patch_shallow(
+		&mut self,
+		new: Node,
+		dst: &mut Vec<(usize, Node)>,
+	) -> Result<(), Error> {
+		self.patch_node(new, Some(dst))
+	}
+
+	// Return child of an element Node by index
+	pub(crate) fn child_mut(&mut self, i: usize) -> Option<&mut Node> {
+		match &mut self.contents {
+			NodeContents::Element(cont) => cont.children.get_mut(i),
+			_ => None,
 		}
+	}
+
+	// Patch self with new. If deferred is set, patching of children of
+	// matching elements is deferred. See patch_shallow().
+	fn patch_node(
+		&mut self,
+		new: Node,
+		deferred: Option<&mut Vec<(usize, Node)>>,
+	) -> Result<(), Error> {
 
 		// Check, if nodes are considered similar enough to be merged and not
 		// replaced destructively
 		if self.key != new.key
-			|| match &self.contents {
-				NodeContents::Text(_) => match &new.contents {
-					NodeContents::Element(_) => true,
-					NodeContents::Text(_) => false,
-				},
-				NodeContents::Element(cont) => match &new.contents {
-					NodeContents::Text(_) => true,
-					NodeContents::Element(new_cont) => new_cont.tag != cont.tag,
-				},
+			|| match (&self.contents, &new.contents) {
+				(NodeContents::Text(_), NodeContents::Text(_)) => false,
+				(NodeContents::Element(old), NodeContents::Element(new)) => {
+					old.tag != new.tag
+				}
+				(NodeContents::Component(old), NodeContents::Component(new)) => {
+					!old.same_type(new)
+				}
+				(NodeContents::Memo(_), NodeContents::Memo(_)) => false,
+				// Hoisted HTML of the same html! site is the same static, so
+				// comparing addresses suffices
+				(NodeContents::Static(old), NodeContents::Static(new)) => {
+					!std::ptr::eq(old.html, new.html)
+				}
+				_ => true,
 			} {
 			return Node::replace_node(self, new);
 		}
@@ -293,10 +765,11 @@ impl Node {
 		self.key = new.key;
 		match &mut self.contents {
 			NodeContents::Text(ref mut old_text) => {
-				if let NodeContents::Text(new_text) = &new.contents {
-					if old_text != new_text {
-						*old_text = new_text.clone();
-						self.element.get()?.set_text_content(Some(old_text));
+				if let NodeContents::Text(new_text) = new.contents {
+					old_text.escaped = new_text.escaped;
+					if old_text.text != new_text.text {
+						old_text.text = new_text.text;
+						self.element.set_text_content(&old_text.text)?;
 					}
 				}
 			}
@@ -304,31 +777,393 @@ impl Node {
 				if let NodeContents::Element(new_cont) = new.contents {
 					old_cont.attrs.patch(&mut self.element, new_cont.attrs)?;
 
+					// Deferred children are patched later, so the flag is
+					// conservatively kept set, if any of them might gain hooks
+					let deferred_hooks = deferred.is_some()
+						&& new_cont
+							.children
+							.iter()
+							.any(|ch| ch.may_have_hooks());
 					Node::patch_children(
 						&mut self.element,
 						&mut old_cont.children,
 						new_cont.children,
+						deferred,
 					)?;
+
+					match (&old_cont.node_ref, &new_cont.node_ref) {
+						(Some(old), Some(new)) if old.ptr_eq(new) => (),
+						(Some(old), _) => old.clear(self.id),
+						_ => (),
+					}
+					if let Some(r) = &new_cont.node_ref {
+						r.set(self.id, self.element.get()?);
+					}
+					old_cont.node_ref = new_cont.node_ref;
+					old_cont.hooks = new_cont.hooks;
+					old_cont.preserve_scroll = new_cont.preserve_scroll;
+					old_cont.update_subtree_hooks();
+					old_cont.subtree_hooks |= deferred_hooks;
+					if let Some(h) = old_cont
+						.hooks
+						.as_ref()
+						.and_then(|h| h.on_update.as_ref())
+					{
+						h(&self.element.get()?);
+					}
+				}
+			}
+			NodeContents::Component(old_comp) => {
+				if let NodeContents::Component(new_comp) = new.contents {
+					old_comp.update(new_comp)?;
+				}
+			}
+			NodeContents::Static(_) => (),
+			NodeContents::Memo(old_memo) => {
+				if let NodeContents::Memo(mut new_memo) = new.contents {
+					if !(old_memo.deps_eq)(&*old_memo.deps, &*new_memo.deps)
+					{
+						let new_node = std::mem::take(new_memo.node());
+						old_memo.deps = new_memo.deps;
+						old_memo.deps_eq = new_memo.deps_eq;
+						old_memo.node().patch(new_node)?;
+					}
 				}
 			}
 		};
 		Ok(())
 	}
 
-	// Completely replace old node and its subtree with new one
-	fn replace_node(&mut self, new: Node) -> Result<(), JsValue> {
-		self.key = new.key;
-		self.contents = new.contents;
-		self.element.get()?.set_outer_html(&self.html()?);
+	// Completely replace old node and its subtree with new one.
+	//
+	// Focus, selection and scroll offsets of keyed elements are restored on the
+	// same keyed elements of the new subtree, if any.
+	fn replace_node(&mut self, new: Node) -> Result<(), Error> {
+		let mut el = self.dom_element()?;
+		let mut unmounted = hooks::Pending::default();
+		self.collect_hooks(Lifecycle::Unmount, &mut unmounted)?;
+
+		let mut preserved = Preserved::default();
+		if let Some(active) = el.active_within()? {
+			let id = active
+				.id()
+				.strip_prefix("bh-")
+				.and_then(|id| id.parse().ok());
+			if let Some(path) = id.and_then(|id| self.keyed_path(id)) {
+				preserved.capture_focus(path, &active);
+			}
+		}
+		self.visit_keyed(&mut Vec::new(), &mut |path, el, opt_in| {
+			if preserved.wants_scroll(path, opt_in) {
+				preserved.capture_scroll(path, &el.get()?);
+			}
+			Ok(())
+		})?;
+
+		*self = new;
+		el.set_outer_html(&self.html()?)?;
+
+		if !preserved.is_empty() {
+			self.visit_keyed(&mut Vec::new(), &mut |path, el, _| {
+				if preserved.has(path) {
+					preserved.restore(path, &el.get()?)?;
+				}
+				Ok(())
+			})?;
+		}
+
+		unmounted.call();
+		self.call_hooks(Lifecycle::Mount)
+	}
+
+	// Like patch(), but recovers from elements of the subtree having been
+	// removed or detached from the DOM by other means, like browser extensions
+	// or third-party scripts.
+	//
+	// Cached elements no longer in the document are dropped before patching.
+	// If an element is then not found during patching, the subtree of its
+	// nearest ancestor element still in the document is re-rendered from the
+	// virtual tree, replacing any DOM the aborted patch left behind. The patch
+	// is then retried with a fresh result of render. Re-rendered elements are
+	// unmounted and mounted again with their lifecycle hooks.
+	pub fn patch_healing<F>(&mut self, mut render: F) -> Result<(), Error>
+	where
+		F: FnMut() -> Node,
+	{
+		self.visit_elements(&mut util::LazyElement::forget_detached);
+
+		let mut healed = Vec::new();
+		loop {
+			let id = match self.patch(render()) {
+				Err(Error::ElementNotFound { id }) => id,
+				res => return res,
+			};
+
+			// Give up, if re-rendering did not help
+			let live = &mut |el: &mut util::LazyElement| el.find().is_some();
+			if healed.contains(&id) || !self.heal(id, live)? {
+				return Err(Error::ElementNotFound { id });
+			}
+			healed.push(id);
+		}
+	}
+
+	// Re-render the subtree of the nearest ancestor of the node with id, that
+	// has its element in the document according to live. Re-renders the whole
+	// subtree, if id is not found in it, as an aborted patch might have
+	// dropped the node already.
+	//
+	// Returns false, if there is no such element to re-render in place of.
+	fn heal(
+		&mut self,
+		id: u64,
+		live: &mut dyn FnMut(&mut util::LazyElement) -> bool,
+	) -> Result<bool, Error> {
+		match self.heal_within(id, live)? {
+			Some(healed) => Ok(healed),
+			None if self.is_live(live) => {
+				self.rerender()?;
+				Ok(true)
+			}
+			None => Ok(false),
+		}
+	}
+
+	// Like heal(), but returns None, if id is not found in the subtree, and
+	// false, if no ancestor of it within the subtree is live
+	fn heal_within(
+		&mut self,
+		id: u64,
+		live: &mut dyn FnMut(&mut util::LazyElement) -> bool,
+	) -> Result<Option<bool>, Error> {
+		match &mut self.contents {
+			NodeContents::Component(c) => return c.node().heal_within(id, live),
+			NodeContents::Memo(m) => return m.node().heal_within(id, live),
+			_ => (),
+		}
+		if self.id == id {
+			return Ok(Some(false));
+		}
+		if let NodeContents::Element(cont) = &mut self.contents {
+			for ch in cont.children.iter_mut() {
+				match ch.heal_within(id, live)? {
+					None => (),
+					Some(true) => return Ok(Some(true)),
+					Some(false) => {
+						if !live(&mut self.element) {
+							return Ok(Some(false));
+						}
+						self.rerender()?;
+						return Ok(Some(true));
+					}
+				}
+			}
+		}
+		Ok(None)
+	}
+
+	// Returns, if the node's own element is in the document according to live
+	fn is_live(
+		&mut self,
+		live: &mut dyn FnMut(&mut util::LazyElement) -> bool,
+	) -> bool {
+		match &mut self.contents {
+			NodeContents::Component(c) => c.node().is_live(live),
+			NodeContents::Memo(m) => m.node().is_live(live),
+			_ => live(&mut self.element),
+		}
+	}
+
+	// Replace the element and subtree in the DOM with fresh HTML of the
+	// virtual tree
+	fn rerender(&mut self) -> Result<(), Error> {
+		let mut el = self.dom_element()?;
+		let mut unmounted = hooks::Pending::default();
+		self.collect_hooks(Lifecycle::Unmount, &mut unmounted)?;
+
+		self.visit_elements(&mut util::LazyElement::reset);
+		el.set_outer_html(&self.html()?)?;
+
+		unmounted.call();
+		self.call_hooks(Lifecycle::Mount)
+	}
+
+	// Call f with the cached DOM element reference of every node of the
+	// subtree
+	fn visit_elements(&mut self, f: &mut dyn FnMut(&mut util::LazyElement)) {
+		f(&mut self.element);
+		match &mut self.contents {
+			NodeContents::Text(_) | NodeContents::Static(_) => (),
+			NodeContents::Element(cont) => {
+				for ch in cont.children.iter_mut() {
+					ch.visit_elements(f);
+				}
+			}
+			NodeContents::Component(c) => c.node().visit_elements(f),
+			NodeContents::Memo(m) => {
+				if let Some(node) = &mut m.node {
+					node.visit_elements(f);
+				}
+			}
+		}
+	}
+
+	// Compare the DOM of the mounted subtree against the virtual tree and
+	// return all divergences found. Contents of static subtrees and text
+	// nodes created without escaping are not checked.
+	//
+	// Looks up every element of the subtree. Intended for debugging.
+	pub fn verify(&mut self) -> Result<Vec<Divergence>, Error> {
+		if !self.is_mounted() {
+			return Err(Error::NotMounted);
+		}
+		let mut dst = Vec::new();
+		self.verify_subtree(&mut dst, &mut util::LazyElement::find);
+		Ok(dst)
+	}
+
+	// Compare the subtree against the elements returned by find, appending
+	// divergences to dst
+	fn verify_subtree<E, F>(&mut self, dst: &mut Vec<Divergence>, find: &mut F)
+	where
+		E: VerifiedElement,
+		F: FnMut(&mut util::LazyElement) -> Option<E>,
+	{
+		let id = self.id;
+		let el = match &mut self.contents {
+			NodeContents::Component(c) => {
+				return c.node().verify_subtree(dst, find)
+			}
+			NodeContents::Memo(m) => {
+				return m.node().verify_subtree(dst, find)
+			}
+			_ => match find(&mut self.element) {
+				Some(el) => el,
+				None => return dst.push(Divergence::Missing { id }),
+			},
+		};
+		match &mut self.contents {
+			// Text written without escaping may contain markup and entities,
+			// that do not map back to the text content
+			NodeContents::Text(text) if text.escaped => {
+				let expected = super::parse::decode_entities(&text.text);
+				let found = el.text();
+				if found != expected {
+					dst.push(Divergence::Text {
+						id,
+						expected,
+						found,
+					});
+				}
+			}
+			NodeContents::Element(cont) => {
+				let expected =
+					tokenizer::get_value(&cont.tag, |t| t.to_owned());
+				let found = el.tag();
+				if found != expected {
+					return dst.push(Divergence::Tag {
+						id,
+						expected,
+						found,
+					});
+				}
+
+				let mut attrs = Vec::<(String, String)>::new();
+				cont.attrs.for_each(|k, v| attrs.push((k.into(), v.into())));
+				for (name, expected) in attrs.iter() {
+					let found = el.attribute(name);
+					if found.as_ref() != Some(expected) {
+						dst.push(Divergence::Attribute {
+							id,
+							name: name.clone(),
+							expected: Some(expected.clone()),
+							found,
+						});
+					}
+				}
+				for name in el.attribute_names() {
+					if name != "id" && !attrs.iter().any(|(k, _)| *k == name) {
+						dst.push(Divergence::Attribute {
+							id,
+							found: el.attribute(&name),
+							name,
+							expected: None,
+						});
+					}
+				}
+
+				let found = el.child_count();
+				if found != cont.children.len() {
+					dst.push(Divergence::Children {
+						id,
+						expected: cont.children.len(),
+						found,
+					});
+				}
+				for ch in cont.children.iter_mut() {
+					ch.verify_subtree(dst, find);
+				}
+			}
+			_ => (),
+		}
+	}
+
+	// Call f with the keys of its keyed ancestors and itself, the lazily
+	// retrieved DOM element and the preserve_scroll flag of every keyed element
+	// node in the subtree
+	fn visit_keyed<F>(
+		&mut self,
+		path: &mut Vec<u64>,
+		f: &mut F,
+	) -> Result<(), Error>
+	where
+		F: FnMut(&[u64], &mut util::LazyElement, bool) -> Result<(), Error>,
+	{
+		match &mut self.contents {
+			NodeContents::Text(_) | NodeContents::Static(_) => (),
+			NodeContents::Element(cont) => {
+				if let Some(key) = self.key {
+					path.push(key);
+					f(path, &mut self.element, cont.preserve_scroll)?;
+				}
+				for ch in cont.children.iter_mut() {
+					ch.visit_keyed(path, f)?;
+				}
+				if self.key.is_some() {
+					path.pop();
+				}
+			}
+			NodeContents::Component(c) => c.node().visit_keyed(path, f)?,
+			NodeContents::Memo(m) => {
+				if let Some(node) = &mut m.node {
+					node.visit_keyed(path, f)?;
+				}
+			}
+		};
 		Ok(())
 	}
 
-	// Diff and patch 2 child lists
+	// Return the keys of the keyed ancestors and itself of the keyed element
+	// node with the element ID in the subtree, if any
+	fn keyed_path(&mut self, id: u64) -> Option<Vec<u64>> {
+		let mut found = None;
+		let _ = self.visit_keyed(&mut Vec::new(), &mut |path, el, _| {
+			if el.id == id {
+				found = Some(path.to_vec());
+			}
+			Ok(())
+		});
+		found
+	}
+
+	// Diff and patch 2 child lists. If deferred is set, patches of matching
+	// children are pushed to it instead of being applied.
 	fn patch_children(
 		parent: &mut util::LazyElement,
 		old: &mut Vec<Node>,
 		new: Vec<Node>,
-	) -> Result<(), JsValue> {
+		mut deferred: Option<&mut Vec<(usize, Node)>>,
+	) -> Result<(), Error> {
 		let mut old_it = old.iter_mut().peekable();
 		let mut new_it = new.into_iter().peekable();
 		let mut i = 0;
@@ -343,12 +1178,21 @@ impl Node {
 					if (old_ch.key.is_some() || new_ch.key.is_some())
 						&& old_ch.key != new_ch.key
 					{
+						if let Some(dst) = deferred {
+							let len = dst.len();
+							dst[len - i..].reverse();
+						}
 						return Node::patch_children_by_key(
 							parent, old, i, new_it,
 						);
 					}
 
-					old_it.next().unwrap().patch(new_it.next().unwrap())?;
+					let old_ch = old_it.next().unwrap();
+					let new_ch = new_it.next().unwrap();
+					match &mut deferred {
+						Some(dst) => dst.push((i, new_ch)),
+						None => old_ch.patch(new_ch)?,
+					};
 					i += 1;
 					continue;
 				}
@@ -356,24 +1200,39 @@ impl Node {
 			break;
 		}
 
+		// Child patches are popped from the end
+		if let Some(dst) = deferred {
+			let len = dst.len();
+			dst[len - i..].reverse();
+		}
+
 		// Handle mismatched node counts using appends or deletes
 		if new_it.peek().is_some() {
 			// Append new nodes to end
 
-			let mut w = util::Appender::new();
+			let mut w = util::pooled_appender();
 			old.reserve(new_it.size_hint().0);
 			for mut new_ch in new_it {
-				new_ch.write_html_to(&mut w).map_err(util::cast_error)?;
+				new_ch.write_html_to(&mut *w)?;
 				old.push(new_ch);
 			}
-			parent.get()?.insert_adjacent_html("beforeend", &w.dump())?;
+			parent.insert_adjacent_html("beforeend", w.join())?;
+
+			let mut mounted = hooks::Pending::default();
+			for ch in old[i..].iter_mut() {
+				ch.collect_hooks(Lifecycle::Mount, &mut mounted)?;
+			}
+			mounted.call();
 		} else if old_it.peek().is_some() {
 			// Remove nodes from end
 
+			let mut unmounted = hooks::Pending::default();
 			for old_ch in old_it {
-				old_ch.element.get()?.remove();
+				old_ch.collect_hooks(Lifecycle::Unmount, &mut unmounted)?;
+				old_ch.dom_element()?.remove()?;
 			}
 			old.truncate(i);
+			unmounted.call();
 		}
 
 		Ok(())
@@ -385,7 +1244,7 @@ impl Node {
 		old: &mut Vec<Node>,
 		mut i: usize,
 		new_it: std::iter::Peekable<std::vec::IntoIter<Node>>,
-	) -> Result<(), JsValue> {
+	) -> Result<(), Error> {
 		// Map old children by key
 		let mut old_by_key = HashMap::<u64, Node>::new();
 		let mut to_remove = Vec::<Node>::new();
@@ -400,9 +1259,30 @@ impl Node {
 			}
 		}
 
+		// Match new children to old ones up front, so unmatched old children
+		// are known before any patching
+		let new: Vec<(Node, Option<Node>)> = new_it
+			.map(|ch| {
+				let old_ch = ch.key.and_then(|k| old_by_key.remove(&k));
+				(ch, old_ch)
+			})
+			.collect();
+
+		// Remove unmatched old children first, so NodeRefs moved from them to
+		// new or patched children are not cleared after being set
+		let mut unmounted = hooks::Pending::default();
+		for mut ch in to_remove
+			.into_iter()
+			.chain(old_by_key.into_values())
+		{
+			ch.collect_hooks(Lifecycle::Unmount, &mut unmounted)?;
+			ch.dom_element()?.remove()?;
+		}
+		unmounted.call();
+
 		// Insert new HTML into the DOM efficiently in buffered chunks
-		old.reserve(new_it.size_hint().0);
-		let mut w = util::Appender::new();
+		old.reserve(new.len());
+		let mut w = util::pooled_appender();
 		let mut buffered = 0;
 
 		let flush = |w: &mut util::Appender,
@@ -410,63 +1290,57 @@ impl Node {
 		             buffered: &mut usize,
 		             old: &mut Vec<Node>,
 		             parent: &mut util::LazyElement|
-		 -> Result<(), JsValue> {
+		 -> Result<(), Error> {
 			if *buffered == 0 {
 				return Ok(());
 			}
 
-			let html = w.dump();
-			w.clear();
+			let html = w.join();
+			// Insert after the last child already in the DOM
 			if *i == 0 {
-				parent.get()?.insert_adjacent_html("afterbegin", &html)?;
+				parent.insert_adjacent_html("afterbegin", html)?;
 			} else {
-				old[*i]
-					.element
-					.get()?
-					.insert_adjacent_html("afterend", &html)?;
+				old[*i - 1]
+					.dom_element()?
+					.insert_adjacent_html("afterend", html)?;
 			}
-			*i += *buffered;
+			w.clear();
+
+			let mut mounted = hooks::Pending::default();
+			for ch in old[*i..*i + *buffered].iter_mut() {
+				ch.collect_hooks(Lifecycle::Mount, &mut mounted)?;
+			}
+			mounted.call();
+
+			*i += *buffered;
 			*buffered = 0;
 
 			Ok(())
 		};
 
-		for mut new_ch in new_it {
-			if let Some(k) = new_ch.key {
-				if let Some(mut old_ch) = old_by_key.remove(&k) {
-					flush(&mut w, &mut i, &mut buffered, old, parent)?;
-
-					let el = old_ch.element.get()?;
-					if i == 0 {
-						parent
-							.get()?
-							.insert_adjacent_element("afterbegin", &el)?;
-					} else {
-						old[i]
-							.element
-							.get()?
-							.insert_adjacent_element("afterend", &el)?;
-					}
-					old_ch.patch(new_ch)?;
-					old.push(old_ch);
-					i += 1;
-					continue;
+		for (mut new_ch, old_ch) in new {
+			if let Some(mut old_ch) = old_ch {
+				flush(&mut w, &mut i, &mut buffered, old, parent)?;
+
+				let mut el = old_ch.dom_element()?;
+				if i == 0 {
+					parent.insert_adjacent_element("afterbegin", &mut el)?;
+				} else {
+					old[i - 1]
+						.dom_element()?
+						.insert_adjacent_element("afterend", &mut el)?;
 				}
+				old_ch.patch(new_ch)?;
+				old.push(old_ch);
+				i += 1;
+				continue;
 			}
-			new_ch.write_html_to(&mut w).map_err(util::cast_error)?;
+			new_ch.write_html_to(&mut *w)?;
 			old.push(new_ch);
 			buffered += 1;
 		}
 		flush(&mut w, &mut i, &mut buffered, old, parent)?;
 
-		// Remove any unmatched old children
-		for mut ch in to_remove
-			.into_iter()
-			.chain(old_by_key.into_iter().map(|(_, v)| v))
-		{
-			ch.element.get()?.remove();
-		}
-
 		Ok(())
 	}
 
@@ -487,52 +1361,301 @@ impl Node {
 	}
 
 	// Format element and subtree as HTML
-	pub fn html(&mut self) -> Result<String, JsValue> {
-		let mut w = util::Appender::new();
-		if let Err(e) = self.write_html_to(&mut w) {
-			return Err(util::cast_error(e));
-		}
-		Ok(w.dump())
+	pub fn html(&mut self) -> Result<String, Error> {
+		let mut w = util::pooled_appender();
+		self.write_html_to(&mut *w)?;
+		Ok(w.take_string())
+	}
+
+	// Format element and subtree as HTML with custom rendering options.
+	//
+	// Nodes rendered without IDs are not mounted by this and can not be
+	// patched, unless mounted separately.
+	pub fn html_with(&mut self, opts: &HtmlOptions) -> Result<String, Error> {
+		let mut w = util::pooled_appender();
+		self.write_html_with(&mut *w, opts, 0, false)?;
+		Ok(w.take_string())
+	}
+}
+
+// Creates an HTML-escaped text node
+impl From<&str> for Node {
+	fn from(text: &str) -> Self {
+		Node::text(&TextOptions {
+			text,
+			escape: true,
+			..Default::default()
+		})
+	}
+}
+
+// Creates an HTML-escaped text node
+impl From<&String> for Node {
+	fn from(text: &String) -> Self {
+		text.as_str().into()
+	}
+}
+
+// Creates an HTML-escaped text node
+impl From<String> for Node {
+	fn from(text: String) -> Self {
+		text.as_str().into()
 	}
 }
 
 impl util::WriteHTMLTo for Node {
 	fn write_html_to<W: fmt::Write>(&mut self, w: &mut W) -> fmt::Result {
+		self.write_html_with(w, &HtmlOptions::default(), 0, false)
+	}
+}
+
+impl Node {
+	// Call f with the root of the rendered subtree of a component or memoized
+	// Node, rendering it, if needed, or the Node itself otherwise. Components
+	// stay borrowed, while f runs.
+	pub(crate) fn with_root<R>(
+		&mut self,
+		f: &mut dyn FnMut(&mut Node) -> R,
+	) -> R {
+		match &mut self.contents {
+			NodeContents::Component(c) => c.node().with_root(f),
+			NodeContents::Memo(m) => m.node().with_root(f),
+			_ => f(self),
+		}
+	}
+
+	// Return children of an element Node
+	pub(crate) fn children_mut(&mut self) -> Option<&mut [Node]> {
+		match &mut self.contents {
+			NodeContents::Element(cont) => Some(&mut cont.children),
+			_ => None,
+		}
+	}
+
+	// Write the opening tag of an element Node with default options and return
+	// its tag for closing the element after its children. Other Nodes and void
+	// elements are written in full.
+	pub(crate) fn write_open<W: fmt::Write>(
+		&mut self,
+		w: &mut W,
+	) -> Result<Option<tokenizer::Token>, fmt::Error> {
+		let is_open = match &self.contents {
+			NodeContents::Element(cont) => !super::parse::is_void(&cont.tag),
+			_ => false,
+		};
+		if !is_open {
+			self.write_html_to(w)?;
+			return Ok(None);
+		}
+
 		self.ensure_id();
+		let id = self.id;
+		match &mut self.contents {
+			NodeContents::Element(cont) => {
+				tokenizer::get_value(&cont.tag, |tag| {
+					write!(w, "<{} id=\"bh-{}\"", tag, id)
+				})?;
+				cont.attrs.write_html_with(w, false)?;
+				w.write_char('>')?;
+				Ok(Some(cont.tag.clone()))
+			}
+			_ => unreachable!(),
+		}
+	}
+}
+
+// Next sibling of a Node, used for determining, if a closing tag can be
+// omitted
+enum Sibling {
+	// Node is the last child of its parent
+	End,
+
+	Element(tokenizer::Token),
+
+	// Static subtree or unknown
+	Other,
+}
+
+impl Node {
+	// Return self as the next sibling of another Node
+	fn as_sibling(&mut self) -> Sibling {
+		match &mut self.contents {
+			NodeContents::Text(_) => Sibling::Element(tokenizer::tag::SPAN),
+			NodeContents::Element(cont) => Sibling::Element(cont.tag.clone()),
+			NodeContents::Component(c) => c.node().as_sibling(),
+			NodeContents::Memo(m) => m.node().as_sibling(),
+			NodeContents::Static(_) => Sibling::Other,
+		}
+	}
+
+	// Write Node and subtree as HTML to w.
+	//
+	// depth is the nesting depth used for indentation. omit_end specifies,
+	// if the closing tag can be omitted, when minifying.
+	fn write_html_with<W: fmt::Write>(
+		&mut self,
+		w: &mut W,
+		opts: &HtmlOptions,
+		depth: usize,
+		omit_end: bool,
+	) -> fmt::Result {
+		match &mut self.contents {
+			NodeContents::Component(c) => {
+				return c.node().write_html_with(w, opts, depth, omit_end)
+			}
+			NodeContents::Memo(m) => {
+				return m.node().write_html_with(w, opts, depth, omit_end)
+			}
+			_ => (),
+		};
+		if opts.ids {
+			self.ensure_id();
+		}
+		if let Some(indent) = opts.indent {
+			for _ in 0..depth {
+				w.write_str(indent)?;
+			}
+		}
+
+		let id = self.id;
+		let write_id = |w: &mut W| {
+			if opts.ids {
+				write!(w, " id=\"bh-{}\"", id)
+			} else {
+				Ok(())
+			}
+		};
 
 		match &mut self.contents {
 			NodeContents::Text(ref text) => {
-				write!(w, "<span id=\"bh-{}\">{}</span>", self.id, text)
+				w.write_str("<span")?;
+				write_id(w)?;
+				write!(w, ">{}</span>", text.text)
+			}
+			NodeContents::Static(StaticContents { html, id_at }) => {
+				w.write_str(&html[..*id_at])?;
+				write_id(w)?;
+				w.write_str(&html[*id_at..])
+			}
+			NodeContents::Component(_) | NodeContents::Memo(_) => {
+				unreachable!()
 			}
 			NodeContents::Element(ref mut cont) => {
-				let id = self.id;
-				tokenizer::get_value(cont.tag, |tag| {
-					write!(w, "<{} id=\"bh-{}\"", tag, id)
-				})?;
-				cont.attrs.write_html_to(w)?;
+				tokenizer::get_value(&cont.tag, |tag| write!(w, "<{}", tag))?;
+				write_id(w)?;
+				cont.attrs.write_html_with(w, opts.minify)?;
 				w.write_char('>')?;
 
-				match cont.tag {
-					// <br>, <hr> and <wbr> must not be closed.
-					// Some browsers will interpret that as 2 tags.
-					36 | 124 | 282 => {
-						return Ok(());
-					}
-					_ => {
-						for ch in cont.children.iter_mut() {
-							ch.write_html_to(w)?;
+				// Void elements like <br> must not be closed.
+				// Some browsers will interpret that as 2 tags.
+				if super::parse::is_void(&cont.tag) {
+					return Ok(());
+				}
+
+				if !cont.children.is_empty() && opts.indent.is_some() {
+					w.write_char('\n')?;
+				}
+				for i in 0..cont.children.len() {
+					let omit_end = opts.minify && {
+						let next = match cont.children.get_mut(i + 1) {
+							Some(next) => next.as_sibling(),
+							None => Sibling::End,
+						};
+						match cont.children[i].as_sibling() {
+							Sibling::Element(tag) => {
+								can_omit_end(&tag, &next, &cont.tag)
+							}
+							_ => false,
 						}
+					};
+					cont.children[i].write_html_with(
+						w,
+						opts,
+						depth + 1,
+						omit_end,
+					)?;
+					if opts.indent.is_some() {
+						w.write_char('\n')?;
 					}
-				};
+				}
 
-				tokenizer::get_value(cont.tag, |tag| write!(w, "</{}>", tag))
+				if omit_end {
+					return Ok(());
+				}
+				if !cont.children.is_empty() {
+					if let Some(indent) = opts.indent {
+						for _ in 0..depth {
+							w.write_str(indent)?;
+						}
+					}
+				}
+				tokenizer::get_value(&cont.tag, |tag| write!(w, "</{}>", tag))
 			}
 		}
 	}
 }
 
+// Returns, if the closing tag of an element can be omitted in minified HTML,
+// given its next sibling and parent.
+// See https://html.spec.whatwg.org/multipage/syntax.html#optional-tags
+fn can_omit_end(
+	tag: &tokenizer::Token,
+	next: &Sibling,
+	parent: &tokenizer::Token,
+) -> bool {
+	use tokenizer::tag::*;
+
+	// Returns, if next is the end of the parent or one of the listed elements
+	macro_rules! followed_by {
+		($($tag:ident)|*) => {
+			match next {
+				Sibling::End => true,
+				Sibling::Element(t) => matches!(*t, $($tag)|*),
+				Sibling::Other => false,
+			}
+		};
+	}
+
+	match *tag {
+		LI => followed_by!(LI),
+		DD => followed_by!(DD | DT),
+		DT => matches!(next, Sibling::Element(DD | DT)),
+		OPTION => followed_by!(OPTION | OPTGROUP),
+		OPTGROUP => followed_by!(OPTGROUP),
+		TR => followed_by!(TR),
+		TD | TH => followed_by!(TD | TH),
+		THEAD => matches!(next, Sibling::Element(TBODY | TFOOT)),
+		TBODY => followed_by!(TBODY | TFOOT),
+		TFOOT => matches!(next, Sibling::End),
+		RT | RP => followed_by!(RT | RP),
+		// Autonomous custom elements have a transparent content model too
+		P => match next {
+			Sibling::End => {
+				!matches!(
+					*parent,
+					A | AUDIO | DEL | INS | MAP | NOSCRIPT | VIDEO
+				) && !tokenizer::get_value(parent, |t| t.contains('-'))
+			}
+			_ => followed_by!(
+				ADDRESS
+					| ARTICLE | ASIDE
+					| BLOCKQUOTE | DETAILS
+					| DIV | DL | FIELDSET
+					| FIGCAPTION | FIGURE
+					| FOOTER | FORM | H1
+					| H2 | H3 | H4 | H5 | H6
+					| HEADER | HGROUP | HR
+					| MAIN | MENU | NAV | OL
+					| P | PRE | SECTION
+					| TABLE | UL
+			),
+		},
+		_ => false,
+	}
+}
+
 #[cfg(test)]
-type TestResult = std::result::Result<(), JsValue>;
+type TestResult = std::result::Result<(), Error>;
 
 #[cfg(test)]
 macro_rules! assert_html {
@@ -631,9 +1754,804 @@ fn element_node_with_children_vec() -> TestResult {
 fn text_node() -> TestResult {
 	let mut node = escaped!("<span>");
 	match &node.contents {
-		NodeContents::Text(t) => assert_eq!(t, "&lt;span&gt;"),
-		_ => assert!(false),
+		NodeContents::Text(t) => assert_eq!(t.text, "&lt;span&gt;"),
+		_ => unreachable!(),
 	};
 	assert_html!(node, r#"<span id="bh-{}">&lt;span&gt;</span>"#, node.id);
 	Ok(())
 }
+
+#[test]
+fn multibyte_attribute_values() -> TestResult {
+	let mut node = element!(
+		"span",
+		{
+			"class" => "кнопка",
+			"title" => "заголовок",
+		}
+	);
+	assert_html!(
+		node,
+		r#"<span id="bh-{}" class="кнопка" title="заголовок"></span>"#,
+		node.id
+	);
+	Ok(())
+}
+
+#[test]
+fn sweep_dropped_nodes() -> TestResult {
+	let mut node = element!(
+		"custom-element",
+		{
+			"class" => "dynamic-class",
+			"data-id" => "1",
+		}
+	);
+	node.html()?;
+	assert_eq!(tokenizer::sweep().reclaimed, 0);
+
+	drop(node);
+	let stats = tokenizer::sweep();
+	assert_eq!(stats.reclaimed, 3);
+	assert_eq!(stats.remaining, 0);
+	Ok(())
+}
+
+#[test]
+fn pre_tokenized_element() -> TestResult {
+	use tokenizer::{attr, tag, Token};
+
+	let class = Token::from("my-class");
+	let mut node = element!(tag::SPAN, {
+		attr::CLASS => class,
+		"data-id" => "1",
+	});
+	assert_html!(
+		node,
+		r#"<span id="bh-{}" class="my-class" data-id="1"></span>"#,
+		node.id
+	);
+	Ok(())
+}
+
+#[test]
+fn memo_skips_unchanged() -> TestResult {
+	use std::cell::Cell;
+	use std::rc::Rc;
+
+	let builds = Rc::new(Cell::new(0));
+	let memo = |deps: (u32, &'static str)| {
+		let builds = builds.clone();
+		Node::memo(deps, move || {
+			builds.set(builds.get() + 1);
+			element!("span", { "class" => "memo" })
+		})
+	};
+
+	let mut node = element!("div", &[], vec![memo((1, "a"))]);
+	assert_eq!(builds.get(), 0);
+	let html = node.html()?;
+	assert_eq!(builds.get(), 1);
+	assert!(html.contains(r#" class="memo"></span>"#));
+
+	// Same deps. Not built or diffed.
+	node.patch(element!("div", &[], vec![memo((1, "a"))]))?;
+	assert_eq!(builds.get(), 1);
+
+	// Changed deps. Built and diffed.
+	node.patch(element!("div", &[], vec![memo((2, "a"))]))?;
+	assert_eq!(builds.get(), 2);
+	assert_eq!(node.html()?, html);
+	Ok(())
+}
+
+#[test]
+fn subtree_hooks() {
+	use std::rc::Rc;
+
+	let hooked = || {
+		Node::element(&ElementOptions {
+			hooks: Hooks {
+				on_mount: Some(Rc::new(|_| ())),
+				..Default::default()
+			},
+			..Default::default()
+		})
+	};
+
+	let plain = element!("div", &[], vec![element!("span"), text!("text")]);
+	assert!(!plain.may_have_hooks());
+
+	let parent = element!("div", &[], vec![element!("span"), hooked()]);
+	assert!(parent.may_have_hooks());
+	let grandparent = element!("div", &[], vec![parent]);
+	assert!(grandparent.may_have_hooks());
+
+	let comp = element!("div", &[], vec![Node::memo((), hooked)]);
+	assert!(comp.may_have_hooks());
+}
+
+#[test]
+fn node_ref_unmounted() -> TestResult {
+	let r = NodeRef::default();
+	let mut node = element!("div", &[], vec![Node::element(&ElementOptions {
+		node_ref: Some(r.clone()),
+		..Default::default()
+	})]);
+	assert!(node.may_have_hooks());
+
+	// Rendering HTML does not mount the Node
+	node.html()?;
+	assert!(r.get().is_none());
+	assert!(r.ptr_eq(&r.clone()));
+	assert!(!r.ptr_eq(&NodeRef::default()));
+	Ok(())
+}
+
+#[test]
+fn html_macro() -> TestResult {
+	let items = ["a", "<b>"];
+	let mut node = crate::html! {
+		<ul class="list" data-x={"y"} key={1}>
+			"<literal>"
+			for item in items.iter() {
+				<li>{ *item }</li>
+			}
+			if items.len() > 2 {
+				<br/>
+			} else {
+				<my-element hidden/>
+			}
+		</ul>
+	};
+	assert_eq!(node.key, Some(1));
+
+	let html = node.html()?;
+	let ids: Vec<u64> = html
+		.split("id=\"bh-")
+		.skip(1)
+		.map(|s| s[..s.find('"').unwrap()].parse().unwrap())
+		.collect();
+	assert_eq!(
+		html,
+		format!(
+			concat!(
+				r#"<ul id="bh-{}" class="list" data-x="y">"#,
+				r#"<span id="bh-{}">&lt;literal&gt;</span>"#,
+				r#"<li id="bh-{}"><span id="bh-{}">a</span></li>"#,
+				r#"<li id="bh-{}"><span id="bh-{}">&lt;b&gt;</span></li>"#,
+				r#"<my-element id="bh-{}" hidden></my-element></ul>"#,
+			),
+			ids[0], ids[1], ids[2], ids[3], ids[4], ids[5], ids[6],
+		)
+	);
+	Ok(())
+}
+
+#[test]
+fn static_subtree() -> TestResult {
+	let render = || crate::html! { <p class="a"><b>"text"</b><br/></p> };
+	let mut node = render();
+	assert!(matches!(node.contents, NodeContents::Static(_)));
+	assert_html!(
+		node,
+		r#"<p id="bh-{}" class="a"><b><span>text</span></b><br></p>"#,
+		node.id
+	);
+
+	// Static subtrees are not diffed
+	let id = node.id;
+	node.patch(render())?;
+	assert_eq!(node.id, id);
+	Ok(())
+}
+
+#[test]
+fn hoisted_matches_dynamic() -> TestResult {
+	let opts = HtmlOptions {
+		ids: false,
+		..Default::default()
+	};
+
+	// Predefined names, duplicates and a non-predefined name sorting last
+	let mut hoisted = crate::html! {
+		<div title="t" class="a" hidden class="b" x-attr="m">
+			<a href="/" class="l"><br/></a>
+		</div>
+	};
+	assert!(matches!(hoisted.contents, NodeContents::Static(_)));
+	let mut dynamic = crate::html! {
+		<div title={"t"} class={"a"} hidden={""} class={"b"} x-attr={"m"}>
+			<a href={"/"} class={"l"}><br/></a>
+		</div>
+	};
+	assert!(!matches!(dynamic.contents, NodeContents::Static(_)));
+	assert_eq!(hoisted.html_with(&opts)?, dynamic.html_with(&opts)?);
+	assert_eq!(
+		hoisted.html_with(&opts)?,
+		concat!(
+			r#"<div class="b" hidden title="t" x-attr="m">"#,
+			r#"<a class="l" href="/"><br></a></div>"#,
+		)
+	);
+
+	// Non-predefined names, whose token order is only known at runtime
+	let mut hoisted = crate::html! {
+		<p data-b="2" id-x="x" data-a="1"></p>
+	};
+	assert!(!matches!(hoisted.contents, NodeContents::Static(_)));
+	let mut dynamic = crate::html! {
+		<p data-b={"2"} id-x={"x"} data-a={"1"}></p>
+	};
+	assert_eq!(hoisted.html_with(&opts)?, dynamic.html_with(&opts)?);
+	Ok(())
+}
+
+#[test]
+fn html_options() -> TestResult {
+	let mut node = element!("div", { "class" => "a b", "title" => "t" }, [
+		element!("ul", &[], vec![
+			element!("li", &[], vec![text!("1")]),
+			element!("li"),
+		]),
+		element!("p", &[], vec![text!("text")]),
+		element!("br"),
+	]);
+
+	assert_eq!(
+		node.html_with(&HtmlOptions {
+			ids: false,
+			indent: Some("  "),
+			..Default::default()
+		})?,
+		concat!(
+			"<div class=\"a b\" title=\"t\">\n",
+			"  <ul>\n",
+			"    <li>\n",
+			"      <span>1</span>\n",
+			"    </li>\n",
+			"    <li></li>\n",
+			"  </ul>\n",
+			"  <p>\n",
+			"    <span>text</span>\n",
+			"  </p>\n",
+			"  <br>\n",
+			"</div>",
+		)
+	);
+	assert!(!node.is_mounted());
+
+	assert_eq!(
+		node.html_with(&HtmlOptions {
+			ids: false,
+			minify: true,
+			..Default::default()
+		})?,
+		concat!(
+			"<div class=\"a b\" title=t>",
+			"<ul><li><span>1</span><li></ul>",
+			"<p><span>text</span></p><br>",
+			"</div>",
+		)
+	);
+
+	// Default options match html()
+	assert_eq!(node.html_with(&Default::default())?, node.html()?);
+	assert!(node.is_mounted());
+	Ok(())
+}
+
+#[test]
+fn minify_p_in_custom_element() -> TestResult {
+	let opts = HtmlOptions {
+		ids: false,
+		minify: true,
+		..Default::default()
+	};
+	let p = || element!("p", &[], vec![text!("a")]);
+
+	let mut node = element!("div", &[], vec![p()]);
+	assert_eq!(node.html_with(&opts)?, "<div><p><span>a</span></div>");
+
+	let mut node = element!("my-el", &[], vec![p()]);
+	assert_eq!(
+		node.html_with(&opts)?,
+		"<my-el><p><span>a</span></p></my-el>"
+	);
+	Ok(())
+}
+
+#[test]
+fn html_steady_state_allocations() -> TestResult {
+	let mut node = crate::element!("ul", {"class" => "list"}, (0..100)
+		.map(|i| {
+			crate::element!("li", {"class" => "item"}, [
+				crate::text!(format!("item {}", i)),
+			])
+		})
+		.collect::<Vec<_>>());
+
+	// Mounts the tree and warms up the buffer pool
+	let html = node.html()?;
+
+	// Only the returned string is allocated
+	let mut res = Ok(String::new());
+	assert_eq!(util::count_allocations(|| res = node.html()), 1);
+	assert_eq!(res?, html);
+
+	// Output fitting into a single chunk is moved out, not copied. At most
+	// the chunk replacing it in the pooled appender is allocated.
+	let mut node = crate::element!("p", &[], vec![crate::text!("a")]);
+	let html = node.html()?;
+	let mut res = Ok(String::new());
+	assert!(util::count_allocations(|| res = node.html()) <= 1);
+	assert_eq!(res?, html);
+	Ok(())
+}
+
+#[test]
+fn patch_unmounted() {
+	let mut node = crate::element!("div");
+	assert!(matches!(
+		node.patch(crate::element!("div")),
+		Err(Error::NotMounted)
+	));
+}
+
+#[test]
+fn verify_unmounted() {
+	let mut node = crate::element!("div");
+	assert!(matches!(node.verify(), Err(Error::NotMounted)));
+	assert!(matches!(
+		node.patch_healing(|| crate::element!("div")),
+		Err(Error::NotMounted)
+	));
+}
+
+// IDs of the element and text nodes of the subtree in document order
+#[cfg(test)]
+fn subtree_ids(node: &Node) -> Vec<u64> {
+	let mut ids = vec![node.id];
+	if let NodeContents::Element(cont) = &node.contents {
+		for ch in cont.children.iter() {
+			ids.extend(subtree_ids(ch));
+		}
+	}
+	ids
+}
+
+#[test]
+fn heal_nearest_live_ancestor() -> TestResult {
+	let log = HookLog::default();
+	let mut node = logged(&log, "div", "root", vec![
+		logged(&log, "ul", "list", vec![
+			logged(&log, "li", "item", vec![]),
+			element!("li"),
+		]),
+		element!("p"),
+	]);
+	node.html()?;
+	let ids = subtree_ids(&node);
+
+	let mut heal = |id: u64, missing: &[u64]| -> Result<_, Error> {
+		let (res, ops) = util::mock_dom(|| {
+			node.heal(id, &mut |el| !missing.contains(&el.id))
+		});
+		let replaced: Vec<u64> = ops
+			.into_iter()
+			.map(|op| match op {
+				util::DomOp::ReplaceHtml(id, _) => id,
+				op => panic!("unexpected {:?}", op),
+			})
+			.collect();
+		let hooks: Vec<String> = log.borrow_mut().drain(..).collect();
+		Ok((res?, replaced, hooks))
+	};
+
+	// Surviving elements are unmounted before being mounted again
+	assert_eq!(
+		heal(ids[2], &[ids[2]])?,
+		(
+			true,
+			vec![ids[1]],
+			vec![
+				"unmount item".into(),
+				"unmount list".into(),
+				"mount item".into(),
+				"mount list".into(),
+			]
+		)
+	);
+
+	// Ancestors, that are missing too, are skipped
+	let (healed, replaced, hooks) = heal(ids[3], &[ids[1], ids[3]])?;
+	assert!(healed);
+	assert_eq!(replaced, [ids[0]]);
+	assert_eq!(hooks.last().unwrap(), "mount root");
+
+	// Node no longer in the virtual tree after an aborted patch
+	assert_eq!(heal(u64::MAX, &[])?.1, [ids[0]]);
+
+	// Nothing to re-render in place of a missing root
+	assert_eq!(heal(ids[0], &[ids[0]])?, (false, vec![], vec![]));
+	assert!(!heal(ids[4], &[ids[0], ids[4]])?.0);
+	assert!(!heal(u64::MAX, &[ids[0]])?.0);
+	Ok(())
+}
+
+// Element with fixed contents for verifying nodes against
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct MockElement {
+	tag: &'static str,
+	text: &'static str,
+	attrs: Vec<(&'static str, &'static str)>,
+	children: usize,
+}
+
+#[cfg(test)]
+impl VerifiedElement for MockElement {
+	fn tag(&self) -> String {
+		self.tag.into()
+	}
+
+	fn text(&self) -> String {
+		self.text.into()
+	}
+
+	fn attribute(&self, name: &str) -> Option<String> {
+		self.attrs
+			.iter()
+			.find(|(k, _)| *k == name)
+			.map(|(_, v)| (*v).into())
+	}
+
+	fn attribute_names(&self) -> Vec<String> {
+		self.attrs.iter().map(|(k, _)| (*k).into()).collect()
+	}
+
+	fn child_count(&self) -> usize {
+		self.children
+	}
+}
+
+#[test]
+fn verify_divergences() -> TestResult {
+	let mut node = element!("div", {"title" => "a"}, [
+		escaped!("a<b"),
+		Node::text(&TextOptions {
+			text: "<i>x</i>",
+			escape: false,
+			..Default::default()
+		}),
+	]);
+	node.html()?;
+	let ids = subtree_ids(&node);
+	let text = |text| MockElement {
+		tag: "span",
+		text,
+		..Default::default()
+	};
+	let mut dom = vec![
+		MockElement {
+			tag: "div",
+			attrs: vec![("id", "bh-0"), ("title", "a")],
+			children: 2,
+			..Default::default()
+		},
+		text("a<b"),
+		text("x"),
+	];
+	let mut verify = |dom: &[MockElement]| {
+		let mut dst = Vec::new();
+		node.verify_subtree(&mut dst, &mut |el: &mut util::LazyElement| {
+			let i = ids.iter().position(|id| *id == el.id).unwrap();
+			dom.get(i).cloned()
+		});
+		dst
+	};
+	assert_eq!(verify(&dom), []);
+
+	// Unescaped text is not compared
+	dom[2].text = "y";
+	assert_eq!(verify(&dom), []);
+
+	dom[1].text = "a";
+	dom[0].attrs = vec![("title", "b"), ("lang", "en")];
+	dom[0].children = 3;
+	assert_eq!(verify(&dom), [
+		Divergence::Attribute {
+			id: ids[0],
+			name: "title".into(),
+			expected: Some("a".into()),
+			found: Some("b".into()),
+		},
+		Divergence::Attribute {
+			id: ids[0],
+			name: "lang".into(),
+			expected: None,
+			found: Some("en".into()),
+		},
+		Divergence::Children {
+			id: ids[0],
+			expected: 2,
+			found: 3,
+		},
+		Divergence::Text {
+			id: ids[1],
+			expected: "a<b".into(),
+			found: "a".into(),
+		},
+	]);
+
+	// Missing elements and subtrees under a different tag
+	dom.pop();
+	assert_eq!(verify(&dom[..2]).last(), Some(&Divergence::Missing {
+		id: ids[2]
+	}));
+	dom[0].tag = "p";
+	assert_eq!(verify(&dom), [Divergence::Tag {
+		id: ids[0],
+		expected: "div".into(),
+		found: "p".into(),
+	}]);
+	Ok(())
+}
+
+#[cfg(test)]
+fn keyed(key: u64) -> Node {
+	let mut node = element!("li");
+	node.key = Some(key);
+	node
+}
+
+#[test]
+fn keyed_insert_after_matched() -> TestResult {
+	use util::DomOp;
+
+	let mut node = element!("ul", &[], vec![keyed(2), keyed(1)]);
+	node.html()?;
+	let ids: Vec<u64> = match &node.contents {
+		NodeContents::Element(cont) => {
+			cont.children.iter().map(|ch| ch.id).collect()
+		}
+		_ => unreachable!(),
+	};
+
+	let (res, ops) = util::mock_dom(|| {
+		node.patch(element!("ul", &[], vec![keyed(1), keyed(3)]))
+	});
+	res?;
+
+	// New child is inserted after the moved one and not after itself
+	let inserted = match &node.contents {
+		NodeContents::Element(cont) => cont.children[1].id,
+		_ => unreachable!(),
+	};
+	assert_eq!(
+		ops,
+		vec![
+			DomOp::Remove(ids[0]),
+			DomOp::Move(ids[1], "afterbegin", node.id),
+			DomOp::InsertHtml(
+				ids[1],
+				"afterend",
+				format!("<li id=\"bh-{}\"></li>", inserted)
+			),
+		]
+	);
+	Ok(())
+}
+
+#[test]
+fn memo_compares_values() -> TestResult {
+	use std::cell::Cell;
+	use std::rc::Rc;
+
+	let builds = Rc::new(Cell::new(0));
+	let counted = |builds: &Rc<Cell<usize>>| {
+		let builds = builds.clone();
+		move || {
+			builds.set(builds.get() + 1);
+			element!("span")
+		}
+	};
+
+	let mut node = element!(
+		"div",
+		&[],
+		vec![Node::memo(String::from("a"), counted(&builds))]
+	);
+	node.html()?;
+	assert_eq!(builds.get(), 1);
+
+	// Deps of a different type are never equal
+	node.patch(element!(
+		"div",
+		&[],
+		vec![Node::memo("a", counted(&builds))]
+	))?;
+	assert_eq!(builds.get(), 2);
+
+	node.patch(element!(
+		"div",
+		&[],
+		vec![Node::memo_hashed("b", counted(&builds))]
+	))?;
+	assert_eq!(builds.get(), 3);
+	node.patch(element!(
+		"div",
+		&[],
+		vec![Node::memo_hashed("b", counted(&builds))]
+	))?;
+	assert_eq!(builds.get(), 3);
+	Ok(())
+}
+
+// Log of lifecycle hook calls of test elements
+#[cfg(test)]
+type HookLog = std::rc::Rc<std::cell::RefCell<Vec<String>>>;
+
+// Element with all lifecycle hooks set to log their calls with name
+#[cfg(test)]
+fn logged(log: &HookLog, tag: &str, name: &str, children: Vec<Node>) -> Node {
+	use std::rc::Rc;
+
+	let hook = |event: &str| -> Option<hooks::Hook> {
+		let log = log.clone();
+		let entry = format!("{} {}", event, name);
+		Some(Rc::new(move |_| log.borrow_mut().push(entry.clone())))
+	};
+	Node::with_children(
+		&ElementOptions {
+			tag: tag.into(),
+			hooks: Hooks {
+				on_mount: hook("mount"),
+				on_update: hook("update"),
+				on_unmount: hook("unmount"),
+			},
+			..Default::default()
+		},
+		children,
+	)
+}
+
+#[test]
+fn subtree_hook_order() -> TestResult {
+	let log = HookLog::default();
+	let tree = |extra: Option<Node>| {
+		let mut children = vec![logged(
+			&log,
+			"div",
+			"a",
+			vec![logged(&log, "div", "b", vec![logged(&log, "p", "c", vec![])])],
+		)];
+		children.extend(extra);
+		element!("section", &[], children)
+	};
+	let mut node = tree(None);
+	node.html()?;
+
+	let mut patch = |new: Node| -> Result<Vec<String>, Error> {
+		util::mock_dom(|| node.patch(new)).0?;
+		Ok(log.borrow_mut().drain(..).collect())
+	};
+	let logged_tree = |name: &str| {
+		logged(&log, "ul", name, vec![logged(&log, "li", "leaf", vec![])])
+	};
+
+	// Descendants are handled before their ancestors
+	assert_eq!(
+		patch(tree(Some(logged_tree("d"))))?,
+		[
+			"update c",
+			"update b",
+			"update a",
+			"mount leaf",
+			"mount d",
+		]
+	);
+	assert_eq!(
+		patch(tree(None))?,
+		["update c", "update b", "update a", "unmount leaf", "unmount d"]
+	);
+
+	// Replaced subtree is unmounted before the new one is mounted
+	assert_eq!(
+		patch(element!("section", &[], vec![logged_tree("e")]))?,
+		["unmount c", "unmount b", "unmount a", "mount leaf", "mount e"]
+	);
+	assert_eq!(
+		patch(element!(
+			"section",
+			&[],
+			vec![logged(&log, "div", "f", vec![])]
+		))?,
+		["unmount leaf", "unmount e", "mount f"]
+	);
+	Ok(())
+}
+
+#[test]
+fn node_ref_moved_across_keyed() -> TestResult {
+	let with_ref = |key: u64, r: &NodeRef| {
+		Node::element(&ElementOptions {
+			tag: "li".into(),
+			key: Some(key),
+			node_ref: Some(r.clone()),
+			..Default::default()
+		})
+	};
+
+	let r = NodeRef::default();
+	let mut node = element!("ul", &[], vec![with_ref(1, &r), keyed(2)]);
+	node.html()?;
+	util::mock_dom(|| node.call_hooks(Lifecycle::Mount)).0?;
+	assert!(r.is_set());
+
+	// Moved to an inserted child
+	util::mock_dom(|| {
+		node.patch(element!("ul", &[], vec![keyed(2), with_ref(3, &r)]))
+	})
+	.0?;
+	assert!(r.is_set());
+
+	// Moved to a matched child
+	util::mock_dom(|| {
+		node.patch(element!("ul", &[], vec![with_ref(2, &r), keyed(4)]))
+	})
+	.0?;
+	assert!(r.is_set());
+
+	util::mock_dom(|| node.patch(element!("ul", &[], vec![keyed(5)]))).0?;
+	assert!(!r.is_set());
+	Ok(())
+}
+
+#[test]
+fn keyed_path() -> TestResult {
+	let mut node = element!(
+		"div",
+		&[],
+		vec![
+			keyed(1),
+			element!("p", &[], vec![text!("a"), keyed(2)]),
+			Node::memo((), || {
+				let mut ch = keyed(3);
+				if let NodeContents::Element(cont) = &mut ch.contents {
+					cont.children.push(element!("b", &[], vec![keyed(4)]));
+				}
+				ch
+			}),
+		]
+	);
+	node.html()?;
+
+	let mut found = Vec::new();
+	node.visit_keyed(&mut Vec::new(), &mut |path, el, _| {
+		found.push((path.to_vec(), el.id));
+		Ok(())
+	})?;
+	assert_eq!(
+		found.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>(),
+		[vec![1], vec![2], vec![3], vec![3, 4]]
+	);
+	for (path, id) in found {
+		assert_eq!(node.keyed_path(id), Some(path));
+	}
+	assert_eq!(node.keyed_path(node.id), None);
+	Ok(())
+}
+
+#[test]
+fn attr_escaping_consistent() -> TestResult {
+	let mut hoisted = crate::html! { <p title="a&b'\"<>"></p> };
+	let mut dynamic = element!("p", { "title" => "a&b'\"<>" });
+	for node in [&mut hoisted, &mut dynamic] {
+		assert_eq!(
+			node.html()?,
+			format!(r#"<p id="bh-{}" title="a&amp;b'&#34;&lt;>"></p>"#, node.id)
+		);
+	}
+	Ok(())
+}
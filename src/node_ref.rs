@@ -0,0 +1,60 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use wasm_bindgen::JsCast;
+
+// Handle to the live DOM element of an element Node.
+//
+// Attach with ElementOptions::node_ref. The handle is filled, when the Node is
+// mounted or patched, and keeps pointing to the current element, when the Node
+// is replaced destructively. It is cleared, when the Node is removed from the
+// DOM, unless the handle has been attached to another Node since.
+#[derive(Clone, Default)]
+pub struct NodeRef(Rc<RefCell<Option<(u64, web_sys::Element)>>>);
+
+impl NodeRef {
+	// Return the DOM element, if the Node is currently mounted
+	pub fn get(&self) -> Option<web_sys::Element> {
+		self.0.borrow().as_ref().map(|(_, el)| el.clone())
+	}
+
+	// Return the DOM element cast to a more specific type, if the Node is
+	// currently mounted and the element is of that type
+	pub fn cast<T: JsCast>(&self) -> Option<T> {
+		self.get()?.dyn_into().ok()
+	}
+
+	// Returns, if both handles point to the same Node
+	pub fn ptr_eq(&self, other: &Self) -> bool {
+		Rc::ptr_eq(&self.0, &other.0)
+	}
+
+	// Point handle to the element of the Node with the ID
+	pub(crate) fn set(&self, id: u64, el: web_sys::Element) {
+		*self.0.borrow_mut() = Some((id, el));
+	}
+
+	// Clear handle, if it still points to the element of the Node with the ID.
+	// The handle might have since been moved to another Node.
+	pub(crate) fn clear(&self, id: u64) {
+		let mut r = self.0.borrow_mut();
+		if matches!(&*r, Some((owner, _)) if *owner == id) {
+			*r = None;
+		}
+	}
+
+	// Returns, if the handle points to an element. Unlike get(), does not
+	// clone the element, which is not possible outside of WASM.
+	#[cfg(test)]
+	pub(crate) fn is_set(&self) -> bool {
+		self.0.borrow().is_some()
+	}
+}
+
+impl fmt::Debug for NodeRef {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("NodeRef")
+			.field(&self.0.borrow().is_some())
+			.finish()
+	}
+}
@@ -0,0 +1,683 @@
+use super::node::{ElementOptions, Node, TextOptions};
+use super::tokenizer::{tag, StrOrToken};
+use super::util;
+use std::fmt;
+
+// Error produced while parsing HTML. Positions are byte offsets into the
+// parsed string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+	// Element not closed before the end of input or its parent's closing tag
+	UnclosedTag { tag: String, position: usize },
+
+	// Closing tag without a matching open element
+	UnexpectedClosingTag { tag: String, position: usize },
+
+	// Malformed tag, attribute or comment
+	InvalidSyntax { position: usize },
+
+	// Element with raw text content, that can not be represented as a Node
+	// tree
+	UnsupportedElement { tag: String, position: usize },
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ParseError::UnclosedTag { tag, position } => {
+				write!(f, "unclosed tag <{}> at {}", tag, position)
+			}
+			ParseError::UnexpectedClosingTag { tag, position } => {
+				write!(f, "unexpected closing tag </{}> at {}", tag, position)
+			}
+			ParseError::InvalidSyntax { position } => {
+				write!(f, "invalid HTML syntax at {}", position)
+			}
+			ParseError::UnsupportedElement { tag, position } => {
+				write!(f, "unsupported element <{}> at {}", tag, position)
+			}
+		}
+	}
+}
+
+impl std::error::Error for ParseError {}
+
+// Elements, that never have any children or closing tags
+static VOID: [&str; 15] = [
+	"area", "base", "br", "col", "embed", "hr", "img", "input", "keygen",
+	"link", "meta", "param", "source", "track", "wbr",
+];
+
+// Elements with raw text content
+static RAW_TEXT: [&str; 5] = ["script", "style", "textarea", "title", "xmp"];
+
+// Elements, whose closing tag can be omitted before their parent's closing tag
+// or the end of input
+static OPTIONAL_END: [&str; 14] = [
+	"dd", "dt", "li", "optgroup", "option", "p", "rp", "rt", "tbody", "td",
+	"tfoot", "th", "thead", "tr",
+];
+
+// Returns, if the opening tag next implicitly closes the open element tag.
+// See https://html.spec.whatwg.org/multipage/syntax.html#optional-tags
+fn closed_by(tag: &str, next: &str) -> bool {
+	match tag {
+		"li" => next == "li",
+		"dd" | "dt" => matches!(next, "dd" | "dt"),
+		"option" => matches!(next, "option" | "optgroup"),
+		"optgroup" => next == "optgroup",
+		"tr" => matches!(next, "tr" | "tbody" | "tfoot"),
+		"td" | "th" => matches!(next, "td" | "th" | "tr" | "tbody" | "tfoot"),
+		"thead" | "tbody" => matches!(next, "tbody" | "tfoot"),
+		"rt" | "rp" => matches!(next, "rt" | "rp"),
+		"p" => matches!(
+			next,
+			"address"
+				| "article" | "aside"
+				| "blockquote" | "details"
+				| "div" | "dl" | "fieldset"
+				| "figcaption" | "figure"
+				| "footer" | "form" | "h1"
+				| "h2" | "h3" | "h4" | "h5" | "h6"
+				| "header" | "hgroup" | "hr"
+				| "main" | "menu" | "nav" | "ol"
+				| "p" | "pre" | "section"
+				| "table" | "ul"
+		),
+		_ => false,
+	}
+}
+
+// Open element being parsed
+struct Open {
+	tag: String,
+	attrs: Vec<(String, String)>,
+	children: Vec<Node>,
+	position: usize,
+}
+
+impl Open {
+	fn into_node(self) -> Node {
+		let attrs: Vec<(StrOrToken, StrOrToken)> = self
+			.attrs
+			.iter()
+			.map(|(k, v)| (k.into(), v.into()))
+			.collect();
+		Node::with_children(
+			&ElementOptions {
+				tag: self.tag.as_str().into(),
+				attrs: &attrs,
+				..Default::default()
+			},
+			self.children,
+		)
+	}
+}
+
+struct Parser<'a> {
+	src: &'a str,
+	pos: usize,
+
+	// Stack of open elements
+	open: Vec<Open>,
+
+	// Parsed top level nodes
+	nodes: Vec<Node>,
+}
+
+impl<'a> Parser<'a> {
+	fn rest(&self) -> &'a str {
+		&self.src[self.pos..]
+	}
+
+	fn invalid(&self) -> ParseError {
+		ParseError::InvalidSyntax { position: self.pos }
+	}
+
+	// Append node to the innermost open element or the top level
+	fn push(&mut self, node: Node) {
+		match self.open.last_mut() {
+			Some(parent) => parent.children.push(node),
+			None => self.nodes.push(node),
+		}
+	}
+
+	fn skip_whitespace(&mut self) {
+		let rest = self.rest();
+		self.pos += rest.len() - rest.trim_start().len();
+	}
+
+	// Returns, if an <svg> element is open. Names of SVG elements and
+	// attributes are case-sensitive.
+	fn in_svg(&self) -> bool {
+		self.open.iter().any(|o| o.tag == "svg")
+	}
+
+	// Close the innermost open elements, while close returns true for them
+	fn close_while<F>(&mut self, mut close: F)
+	where
+		F: FnMut(&Open) -> bool,
+	{
+		while let Some(open) = self.open.last() {
+			if !close(open) {
+				break;
+			}
+			let open = self.open.pop().unwrap();
+			self.push(open.into_node());
+		}
+	}
+
+	// Consume and return a tag or attribute name. HTML names are lowercased.
+	fn name(&mut self, lowercase: bool) -> Result<String, ParseError> {
+		let len = self
+			.rest()
+			.find(|c: char| {
+				c.is_whitespace() || matches!(c, '>' | '/' | '=' | '"' | '\'')
+			})
+			.unwrap_or_else(|| self.rest().len());
+		if len == 0 {
+			return Err(self.invalid());
+		}
+		let mut name = self.rest()[..len].to_owned();
+		if lowercase {
+			name.make_ascii_lowercase();
+		}
+		self.pos += len;
+		Ok(name)
+	}
+
+	fn parse(mut self) -> Result<Vec<Node>, ParseError> {
+		while !self.rest().is_empty() {
+			let rest = self.rest();
+			if rest.starts_with("<!--") {
+				match rest.find("-->") {
+					Some(i) => self.pos += i + 3,
+					None => return Err(self.invalid()),
+				}
+			} else if rest.starts_with("<!") || rest.starts_with("<?") {
+				// Doctype or processing instruction
+				match rest.find('>') {
+					Some(i) => self.pos += i + 1,
+					None => return Err(self.invalid()),
+				}
+			} else if rest.starts_with("</") && is_markup(rest) {
+				self.closing_tag()?;
+			} else if is_markup(rest) {
+				self.opening_tag()?;
+			} else {
+				let len = rest
+					.match_indices('<')
+					.map(|(i, _)| i)
+					.find(|i| is_markup(&rest[*i..]))
+					.unwrap_or(rest.len());
+				let text = &rest[..len];
+				self.pos += len;
+
+				// Text nodes are rendered wrapped in <span>, so whitespace
+				// between elements is dropped to preserve layout
+				if !text.trim().is_empty() {
+					self.push(Node::text(&TextOptions {
+						text: &escape_text(text),
+						escape: false,
+						..Default::default()
+					}));
+				}
+			}
+		}
+
+		self.close_while(|o| OPTIONAL_END.contains(&o.tag.as_str()));
+		match self.open.pop() {
+			Some(open) => Err(ParseError::UnclosedTag {
+				tag: open.tag,
+				position: open.position,
+			}),
+			None => Ok(self.nodes),
+		}
+	}
+
+	fn opening_tag(&mut self) -> Result<(), ParseError> {
+		let position = self.pos;
+		self.pos += 1;
+		let in_svg = self.in_svg();
+		let tag = self.name(!in_svg)?;
+
+		// SVG has no raw text or void elements
+		let svg = in_svg || tag == "svg";
+		if !svg {
+			if RAW_TEXT.contains(&tag.as_str()) {
+				return Err(ParseError::UnsupportedElement { tag, position });
+			}
+			self.close_while(|o| closed_by(&o.tag, &tag));
+		}
+
+		let mut attrs = Vec::new();
+		let self_closing = loop {
+			self.skip_whitespace();
+			let rest = self.rest();
+			if rest.starts_with("/>") {
+				self.pos += 2;
+				break true;
+			}
+			if rest.starts_with('>') {
+				self.pos += 1;
+				break false;
+			}
+			if rest.is_empty() {
+				return Err(self.invalid());
+			}
+
+			let key = self.name(!svg)?;
+			self.skip_whitespace();
+			let value = if self.rest().starts_with('=') {
+				self.pos += 1;
+				self.skip_whitespace();
+				decode_entities(self.attribute_value()?)
+			} else {
+				String::new()
+			};
+
+			// Element IDs are managed by brunhild
+			if key != "id" {
+				attrs.push((key, value));
+			}
+		};
+
+		let open = Open {
+			tag,
+			attrs,
+			children: Vec::new(),
+			position,
+		};
+		if self_closing || !svg && VOID.contains(&open.tag.as_str()) {
+			self.push(open.into_node());
+		} else {
+			self.open.push(open);
+		}
+		Ok(())
+	}
+
+	// Consume and return a quoted or unquoted attribute value
+	fn attribute_value(&mut self) -> Result<&'a str, ParseError> {
+		let rest = self.rest();
+		let (start, len) = match rest.chars().next() {
+			Some(q @ '"') | Some(q @ '\'') => match rest[1..].find(q) {
+				Some(len) => (1, len),
+				None => return Err(self.invalid()),
+			},
+			_ => (
+				0,
+				rest.find(|c: char| c.is_whitespace() || c == '>')
+					.unwrap_or(rest.len()),
+			),
+		};
+		self.pos += len + start * 2;
+		Ok(&rest[start..start + len])
+	}
+
+	fn closing_tag(&mut self) -> Result<(), ParseError> {
+		let position = self.pos;
+		self.pos += 2;
+		let in_svg = self.in_svg();
+		let tag = self.name(!in_svg)?;
+		self.skip_whitespace();
+		if !self.rest().starts_with('>') {
+			return Err(self.invalid());
+		}
+		self.pos += 1;
+
+		// Closing void tags like </br> are tolerated by browsers
+		if !in_svg && VOID.contains(&tag.as_str()) {
+			return Ok(());
+		}
+
+		// Close elements with omitted closing tags inside the closed one
+		if self.open.iter().any(|o| o.tag.eq_ignore_ascii_case(&tag)) {
+			self.close_while(|o| {
+				!o.tag.eq_ignore_ascii_case(&tag)
+					&& OPTIONAL_END.contains(&o.tag.as_str())
+			});
+		}
+		match self.open.pop() {
+			Some(open) if open.tag.eq_ignore_ascii_case(&tag) => {
+				self.push(open.into_node());
+				Ok(())
+			}
+			Some(open) => Err(ParseError::UnclosedTag {
+				tag: open.tag,
+				position: open.position,
+			}),
+			None => Err(ParseError::UnexpectedClosingTag { tag, position }),
+		}
+	}
+}
+
+// Returns, if s starts with a tag, comment, doctype or processing
+// instruction. Like browsers, a '<' not followed by a name character is text.
+fn is_markup(s: &str) -> bool {
+	let mut b = s.bytes();
+	b.next() == Some(b'<')
+		&& match b.next() {
+			Some(b'!') | Some(b'?') => true,
+			Some(b'/') => matches!(b.next(), Some(c) if c.is_ascii_alphabetic()),
+			Some(c) => c.is_ascii_alphabetic(),
+			None => false,
+		}
+}
+
+// Decode the character reference at the start of s. Returns the character
+// and the length of the reference.
+fn decode_reference(s: &str) -> Option<(char, usize)> {
+	let end = s.find(';')?;
+	let ch = match &s[1..end] {
+		"amp" => '&',
+		"lt" => '<',
+		"gt" => '>',
+		"quot" => '"',
+		"apos" => '\'',
+		"nbsp" => '\u{a0}',
+		name => {
+			let num = name.strip_prefix('#')?;
+			match num.strip_prefix(['x', 'X']) {
+				Some(hex) => u32::from_str_radix(hex, 16).ok(),
+				None => num.parse().ok(),
+			}
+			.and_then(std::char::from_u32)?
+		}
+	};
+	Some((ch, end + 1))
+}
+
+// Return the length of the named character reference at the start of s, if
+// any, regardless of it being known to decode_reference()
+fn named_reference(s: &str) -> Option<usize> {
+	let name = s.strip_prefix('&')?;
+	let len = name
+		.find(|c: char| !c.is_ascii_alphanumeric())
+		.filter(|len| *len > 0 && name[*len..].starts_with(';'))?;
+	if name.as_bytes()[0].is_ascii_alphabetic() {
+		Some(len + 2)
+	} else {
+		None
+	}
+}
+
+// Decode HTML character references. Unknown references are kept as is.
+pub(crate) fn decode_entities(s: &str) -> String {
+	let mut out = String::with_capacity(s.len());
+	let mut rest = s;
+	while let Some(i) = rest.find('&') {
+		out += &rest[..i];
+		rest = &rest[i..];
+		match decode_reference(rest) {
+			Some((ch, len)) => {
+				out.push(ch);
+				rest = &rest[len..];
+			}
+			None => {
+				out.push('&');
+				rest = &rest[1..];
+			}
+		}
+	}
+	out += rest;
+	out
+}
+
+// Normalize text to HTML-escaped form. Unlike decode_entities(), named
+// references not known to it, like &copy;, are kept as is, so the browser
+// decodes them instead of them being escaped again.
+fn escape_text(s: &str) -> String {
+	let mut out = String::with_capacity(s.len());
+	let mut rest = s;
+	while let Some(i) = rest.find('&') {
+		out += &util::html_escape(&rest[..i]);
+		rest = &rest[i..];
+		let len = if let Some((ch, len)) = decode_reference(rest) {
+			out += &util::html_escape(ch.encode_utf8(&mut [0; 4]));
+			len
+		} else if let Some(len) = named_reference(rest) {
+			out += &rest[..len];
+			len
+		} else {
+			out += "&amp;";
+			1
+		};
+		rest = &rest[len..];
+	}
+	out += &util::html_escape(rest);
+	out
+}
+
+impl Node {
+	// Parse HTML into a list of Nodes, that can be mounted or patched into
+	// existing Nodes. Tags and attributes are tokenized like with
+	// Node::element().
+	//
+	// Comments, doctypes and whitespace-only text between elements are
+	// skipped. ID attributes are ignored, as element IDs are managed by
+	// brunhild. Elements with raw text content like <script> and <textarea>
+	// are not supported.
+	pub fn parse_html(html: &str) -> Result<Vec<Node>, ParseError> {
+		Parser {
+			src: html,
+			pos: 0,
+			open: Vec::new(),
+			nodes: Vec::new(),
+		}
+		.parse()
+	}
+}
+
+// Returns, if tag is a void element, that must not be closed
+pub(crate) fn is_void(t: &super::tokenizer::Token) -> bool {
+	matches!(
+		*t,
+		tag::AREA
+			| tag::BASE
+			| tag::BR
+			| tag::COL
+			| tag::EMBED
+			| tag::HR
+			| tag::IMG
+			| tag::INPUT
+			| tag::KEYGEN
+			| tag::LINK
+			| tag::META
+			| tag::PARAM
+			| tag::SOURCE
+			| tag::TRACK
+			| tag::WBR
+	)
+}
+
+#[cfg(test)]
+fn render(html: &str) -> Result<String, ParseError> {
+	let mut out = String::new();
+	for mut node in Node::parse_html(html)? {
+		out += &node.html().unwrap();
+	}
+
+	// Strip generated IDs
+	let mut stripped = String::new();
+	let mut rest = out.as_str();
+	while let Some(i) = rest.find(" id=\"bh-") {
+		stripped += &rest[..i];
+		rest = &rest[i + 8..];
+		rest = &rest[rest.find('"').unwrap() + 1..];
+	}
+	stripped += rest;
+	Ok(stripped)
+}
+
+#[test]
+fn parse_elements() {
+	assert_eq!(
+		render(concat!(
+			"<!DOCTYPE html>\n",
+			"<DIV class=\"a\" id=\"x\" data-v='b' hidden>\n",
+			"\t<!-- comment -->\n",
+			"\t<p>text &amp; more&#33; &#x3C;&unknown;</p>\n",
+			"\t<img src=a.png><br/></br><my-element />\n",
+			"</div>\n",
+			"tail",
+		))
+		.unwrap(),
+		concat!(
+			r#"<div class="a" hidden data-v="b">"#,
+			"<p><span>text &amp; more! &lt;&unknown;</span></p>",
+			r#"<img src="a.png"><br><my-element></my-element>"#,
+			"</div><span>\ntail</span>",
+		)
+	);
+}
+
+#[test]
+fn parse_named_references() {
+	// Named references are not escaped again
+	assert_eq!(
+		render("<p>&copy; 2020 &mdash; x &amp;copy; &AMP &#38;x;</p>").unwrap(),
+		"<p><span>&copy; 2020 &mdash; x &amp;copy; &amp;AMP &amp;x;</span></p>"
+	);
+	assert_eq!(
+		render("<p>a & b &1; &;</p>").unwrap(),
+		"<p><span>a &amp; b &amp;1; &amp;;</span></p>"
+	);
+}
+
+#[test]
+fn parse_errors() {
+	assert_eq!(
+		render("<div><span></div>"),
+		Err(ParseError::UnclosedTag {
+			tag: "span".into(),
+			position: 5,
+		})
+	);
+	assert_eq!(
+		render("<div>"),
+		Err(ParseError::UnclosedTag {
+			tag: "div".into(),
+			position: 0,
+		})
+	);
+	assert_eq!(
+		render("a</p>"),
+		Err(ParseError::UnexpectedClosingTag {
+			tag: "p".into(),
+			position: 1,
+		})
+	);
+	assert_eq!(
+		render("<div class=\"a></div>"),
+		Err(ParseError::InvalidSyntax { position: 11 })
+	);
+	assert_eq!(
+		render("<div><script></script></div>"),
+		Err(ParseError::UnsupportedElement {
+			tag: "script".into(),
+			position: 5,
+		})
+	);
+}
+
+#[test]
+fn parse_optional_end_tags() {
+	assert_eq!(
+		render(concat!(
+			"<ul><li>a<li><b>b</b></ul>",
+			"<dl><dt>a<dd>b<dt>c</dl>",
+			"<table><tbody><tr><td>a<th>b<tr><td>c</table>",
+			"<p>a<div>b</div><p>c",
+		))
+		.unwrap(),
+		concat!(
+			"<ul><li><span>a</span></li><li><b><span>b</span></b></li></ul>",
+			"<dl><dt><span>a</span></dt><dd><span>b</span></dd>",
+			"<dt><span>c</span></dt></dl>",
+			"<table><tbody><tr><td><span>a</span></td>",
+			"<th><span>b</span></th></tr>",
+			"<tr><td><span>c</span></td></tr></tbody></table>",
+			"<p><span>a</span></p><div><span>b</span></div>",
+			"<p><span>c</span></p>",
+		)
+	);
+
+	// Minified output of the renderer is read back. Text is left out, as it
+	// is wrapped in <span> again on parsing.
+	let mut node = crate::element!("div", &[], vec![
+		crate::element!("ul", &[], vec![
+			crate::element!("li", &[], vec![crate::element!("em")]),
+			crate::element!("li", &[], vec![crate::element!("em")]),
+		]),
+		crate::element!("p", &[], vec![crate::element!("em")]),
+	]);
+	let write = |node: &mut Node, minify: bool| {
+		node.html_with(&crate::HtmlOptions {
+			ids: false,
+			minify,
+			..Default::default()
+		})
+		.unwrap()
+	};
+	let html = write(&mut node, true);
+	assert_eq!(
+		html,
+		"<div><ul><li><em></em><li><em></em></ul><p><em></em></div>"
+	);
+	let mut parsed = Node::parse_html(&html).unwrap();
+	assert_eq!(parsed.len(), 1);
+	assert_eq!(write(&mut parsed[0], false), write(&mut node, false));
+}
+
+#[test]
+fn parse_svg_case() {
+	assert_eq!(
+		render(concat!(
+			r#"<SVG viewBox="0 0 1 1"><foreignObject><title>a</title>"#,
+			r#"</foreignObject><path/></SVG><DIV CLASS="a"></DIV>"#,
+		))
+		.unwrap(),
+		concat!(
+			r#"<svg viewBox="0 0 1 1"><foreignObject><title><span>a</span>"#,
+			r#"</title></foreignObject><path></path></svg>"#,
+			r#"<div class="a"></div>"#,
+		)
+	);
+}
+
+#[test]
+fn parse_bare_less_than() {
+	assert_eq!(
+		render("<p>a < b <3</p>1 <= 2 <").unwrap(),
+		"<p><span>a &lt; b &lt;3</span></p><span>1 &lt;= 2 &lt;</span>"
+	);
+}
+
+#[test]
+fn attribute_round_trip() {
+	let src = concat!(
+		r#"<a title="&quot; onclick=&quot;alert(1)" "#,
+		r#"href="?a=1&amp;b=2"></a>"#,
+	);
+	let html = render(src).unwrap();
+	assert_eq!(
+		html,
+		concat!(
+			r#"<a href="?a=1&amp;b=2" "#,
+			r#"title="&#34; onclick=&#34;alert(1)"></a>"#,
+		)
+	);
+	assert_eq!(render(&html).unwrap(), html);
+}
+
+#[test]
+fn patch_parsed() -> Result<(), crate::Error> {
+	let parse = || Node::parse_html("<ul><li>a</li><li>b</li></ul>").unwrap();
+	let mut node = parse().pop().unwrap();
+	let html = node.html()?;
+
+	// Identical parsed trees produce no changes
+	node.patch(parse().pop().unwrap())?;
+	assert_eq!(node.html()?, html);
+	Ok(())
+}
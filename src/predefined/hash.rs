@@ -0,0 +1,91 @@
+// Hash function of the predefined token perfect hash table. Included by both
+// build.rs and the tokenizer to guarantee they always agree.
+//
+// Returns a bucket hash and 2 displacement hashes.
+#[allow(dead_code)]
+const fn phf_hash(seed: u64, s: &[u8]) -> (u32, u32, u32) {
+	// FNV-1a
+	let mut h = 0xcbf2_9ce4_8422_2325 ^ seed;
+	let mut i = 0;
+	while i < s.len() {
+		h ^= s[i] as u64;
+		h = h.wrapping_mul(0x0100_0000_01b3);
+		i += 1;
+	}
+
+	// MurmurHash3 finalizer to spread the bits
+	h ^= h >> 33;
+	h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+	h ^= h >> 33;
+	h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+	h ^= h >> 33;
+
+	(
+		(h >> 42) as u32,
+		((h >> 21) & 0x1f_ffff) as u32,
+		(h & 0x1f_ffff) as u32,
+	)
+}
+
+// Compute table slot from displacement hashes and bucket displacements
+#[allow(dead_code)]
+const fn phf_slot(f1: u32, f2: u32, d1: u32, d2: u32, len: usize) -> usize {
+	(f2.wrapping_add(f1.wrapping_mul(d1)).wrapping_add(d2) % len as u32)
+		as usize
+}
+
+// Build perfect hash table of keys using the hash and displace algorithm.
+// See http://cmph.sourceforge.net/papers/esa09.pdf
+//
+// Returns the seed, bucket displacements and slots containing the 1-based
+// indexes of keys. Keys must be unique.
+#[allow(dead_code)]
+fn phf_build(keys: &[&str]) -> (u64, Vec<(u32, u32)>, Vec<u32>) {
+	const LAMBDA: usize = 5;
+
+	let len = keys.len().next_power_of_two();
+	let bucket_count = keys.len().div_ceil(LAMBDA).max(1);
+
+	'seeds: for seed in 0.. {
+		let hashes: Vec<(u32, u32, u32)> =
+			keys.iter().map(|k| phf_hash(seed, k.as_bytes())).collect();
+
+		let mut buckets = vec![Vec::<usize>::new(); bucket_count];
+		for (i, (g, _, _)) in hashes.iter().enumerate() {
+			buckets[*g as usize % bucket_count].push(i);
+		}
+
+		// Place largest buckets first, as they are the hardest to fit
+		let mut order: Vec<usize> = (0..bucket_count).collect();
+		order.sort_by(|a, b| buckets[*b].len().cmp(&buckets[*a].len()));
+
+		let mut slots = vec![0u32; len];
+		let mut displacements = vec![(0u32, 0u32); bucket_count];
+		let mut taken = Vec::with_capacity(LAMBDA * 2);
+		'buckets: for b in order {
+			for d1 in 0..len as u32 {
+				'displace: for d2 in 0..len as u32 {
+					taken.clear();
+					for i in buckets[b].iter() {
+						let (_, f1, f2) = hashes[*i];
+						let slot = phf_slot(f1, f2, d1, d2, len);
+						if slots[slot] != 0 || taken.contains(&slot) {
+							continue 'displace;
+						}
+						taken.push(slot);
+					}
+
+					for (slot, i) in taken.iter().zip(buckets[b].iter()) {
+						slots[*slot] = *i as u32 + 1;
+					}
+					displacements[b] = (d1, d2);
+					continue 'buckets;
+				}
+			}
+			continue 'seeds;
+		}
+
+		return (seed, displacements, slots);
+	}
+	unreachable!()
+}
@@ -0,0 +1,153 @@
+use super::error::Error;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Element, HtmlElement, HtmlInputElement, HtmlTextAreaElement};
+
+// Text selection inside an input or textarea element
+struct Selection {
+	start: u32,
+	end: u32,
+	direction: String,
+}
+
+impl Selection {
+	// Capture selection of element, if it is a text input or textarea
+	fn capture(el: &Element) -> Option<Self> {
+		// Not all input types support selection and throw on access
+		let (start, end, direction) =
+			if let Some(el) = el.dyn_ref::<HtmlInputElement>() {
+				(
+					el.selection_start().ok()??,
+					el.selection_end().ok()??,
+					el.selection_direction().ok()?,
+				)
+			} else if let Some(el) = el.dyn_ref::<HtmlTextAreaElement>() {
+				(
+					el.selection_start().ok()??,
+					el.selection_end().ok()??,
+					el.selection_direction().ok()?,
+				)
+			} else {
+				return None;
+			};
+		Some(Self {
+			start,
+			end,
+			direction: direction.unwrap_or_else(|| "none".into()),
+		})
+	}
+
+	// Restore selection on element, if it is a text input or textarea
+	fn restore(&self, el: &Element) -> Result<(), JsValue> {
+		if let Some(el) = el.dyn_ref::<HtmlInputElement>() {
+			el.set_selection_range_with_direction(
+				self.start,
+				self.end,
+				&self.direction,
+			)
+		} else if let Some(el) = el.dyn_ref::<HtmlTextAreaElement>() {
+			el.set_selection_range_with_direction(
+				self.start,
+				self.end,
+				&self.direction,
+			)
+		} else {
+			Ok(())
+		}
+	}
+}
+
+// Focus, selection and scroll state of keyed elements in a subtree, captured
+// before destructive replacement of the subtree.
+//
+// Elements are identified by the keys of all their keyed ancestors within the
+// subtree and their own key.
+//
+// Reading scroll offsets forces layout, so they are only captured for the
+// focused element, its keyed ancestors and elements opted in with
+// ElementOptions::preserve_scroll.
+#[derive(Default)]
+pub struct Preserved {
+	// Focused element and its selection
+	focused: Option<(Vec<u64>, Option<Selection>)>,
+
+	// Elements with non-zero scroll offsets and their top and left offsets
+	scrolled: Vec<(Vec<u64>, i32, i32)>,
+}
+
+impl Preserved {
+	// Capture focus and selection of the focused keyed element
+	pub fn capture_focus(&mut self, path: Vec<u64>, el: &Element) {
+		self.focused = Some((path, Selection::capture(el)));
+	}
+
+	// Returns, if the scroll offsets of a keyed element should be captured.
+	// opt_in is set, if the element has ElementOptions::preserve_scroll set.
+	pub fn wants_scroll(&self, path: &[u64], opt_in: bool) -> bool {
+		opt_in
+			|| matches!(&self.focused, Some((p, _)) if p.starts_with(path))
+	}
+
+	// Capture scroll offsets of a keyed element, if non-zero
+	pub fn capture_scroll(&mut self, path: &[u64], el: &Element) {
+		let (top, left) = (el.scroll_top(), el.scroll_left());
+		if top != 0 || left != 0 {
+			self.scrolled.push((path.into(), top, left));
+		}
+	}
+
+	// Returns, if there is no captured state to restore
+	pub fn is_empty(&self) -> bool {
+		self.focused.is_none() && self.scrolled.is_empty()
+	}
+
+	// Returns, if a keyed element has any captured state to restore
+	pub fn has(&self, path: &[u64]) -> bool {
+		matches!(&self.focused, Some((p, _)) if p.as_slice() == path)
+			|| self.scrolled.iter().any(|(p, ..)| p.as_slice() == path)
+	}
+
+	// Restore state of a keyed element in the replacement subtree, if it had
+	// any captured
+	pub fn restore(&self, path: &[u64], el: &Element) -> Result<(), Error> {
+		for (p, top, left) in self.scrolled.iter() {
+			if p.as_slice() == path {
+				el.set_scroll_top(*top);
+				el.set_scroll_left(*left);
+			}
+		}
+		if let Some((p, sel)) = &self.focused {
+			if p.as_slice() == path {
+				if let Some(el) = el.dyn_ref::<HtmlElement>() {
+					el.focus()?;
+				}
+				if let Some(sel) = sel {
+					sel.restore(el)?;
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+#[test]
+fn path_matching() {
+	let mut p = Preserved::default();
+	assert!(p.is_empty());
+	assert!(!p.wants_scroll(&[1], false));
+	assert!(p.wants_scroll(&[1], true));
+
+	p.focused = Some((vec![1, 2, 3], None));
+	assert!(!p.is_empty());
+	for path in [&[1][..], &[1, 2], &[1, 2, 3]] {
+		assert!(p.wants_scroll(path, false));
+	}
+	for path in [&[2][..], &[1, 3], &[1, 2, 3, 4], &[2, 3]] {
+		assert!(!p.wants_scroll(path, false));
+	}
+
+	p.scrolled.push((vec![4], 10, 0));
+	assert!(p.has(&[1, 2, 3]));
+	assert!(p.has(&[4]));
+	assert!(!p.has(&[1, 2]));
+	assert!(!p.has(&[4, 1]));
+}
@@ -0,0 +1,311 @@
+use super::node::Node;
+use super::tokenizer::{self, Token};
+use super::util::WriteHTMLTo;
+use std::fmt::{self, Write};
+use std::future::Future;
+use std::io;
+
+// Default size of chunks produced by Node::render_to_async()
+const CHUNK_SIZE: usize = 8 << 10;
+
+// Adapts io::Write to fmt::Write, storing any I/O error
+struct IoAdapter<'a, W: io::Write> {
+	w: &'a mut W,
+	err: Option<io::Error>,
+}
+
+impl<'a, W: io::Write> fmt::Write for IoAdapter<'a, W> {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		self.w.write_all(s.as_bytes()).map_err(|e| {
+			self.err = Some(e);
+			fmt::Error
+		})
+	}
+}
+
+// Iterator over chunks of the HTML of a Node and its subtree. Rendering
+// progresses only as chunks are consumed.
+//
+// Created with Node::html_chunks(). Components in the subtree are borrowed only
+// while a chunk is being written.
+pub struct HtmlChunks<'a> {
+	// Minimum size of a chunk
+	size: usize,
+
+	// Chunk being written
+	buf: String,
+
+	root: &'a mut Node,
+
+	// Open elements starting with a frame for the root, that has no tag
+	stack: Vec<Frame>,
+}
+
+// Open element being written by HtmlChunks
+struct Frame {
+	// Index of the next child to write. The element is the child before the
+	// next one of the parent frame.
+	next: usize,
+
+	// Tag to close the element with. None for the root.
+	tag: Option<Token>,
+}
+
+// Call f with the children of the element at the end of the path of frames
+// starting at children. Components and memoized subtrees on the path are
+// resolved to their rendered roots. Returns None, if the path does not lead to
+// an element.
+fn with_children<R>(
+	children: &mut [Node],
+	path: &[Frame],
+	f: &mut dyn FnMut(&mut [Node]) -> R,
+) -> Option<R> {
+	match path.split_first() {
+		None => Some(f(children)),
+		Some((frame, rest)) => children
+			.get_mut(frame.next.checked_sub(1)?)?
+			.with_root(&mut |node| {
+				with_children(node.children_mut()?, rest, &mut *f)
+			}),
+	}
+}
+
+impl<'a> Iterator for HtmlChunks<'a> {
+	type Item = String;
+
+	fn next(&mut self) -> Option<String> {
+		let HtmlChunks {
+			size,
+			buf,
+			root,
+			stack,
+		} = self;
+		while buf.len() < *size {
+			let (top, path) = match stack.split_last_mut() {
+				Some(split) => split,
+				None => break,
+			};
+
+			// Write the next child of the innermost open element, if any
+			let next = top.next;
+			let written = with_children(
+				std::slice::from_mut(*root),
+				path,
+				&mut |children| {
+					children.get_mut(next).map(|node| {
+						node.with_root(&mut |node| node.write_open(buf))
+					})
+				},
+			)
+			.flatten();
+
+			// Writing to a String never fails
+			match written {
+				Some(res) => {
+					top.next += 1;
+					if let Some(tag) = res.unwrap() {
+						stack.push(Frame {
+							next: 0,
+							tag: Some(tag),
+						});
+					}
+				}
+				None => {
+					if let Some(Frame { tag: Some(tag), .. }) = stack.pop() {
+						tokenizer::get_value(&tag, |tag| {
+							write!(buf, "</{}>", tag)
+						})
+						.unwrap();
+					}
+				}
+			}
+		}
+
+		if buf.is_empty() {
+			None
+		} else {
+			Some(std::mem::replace(buf, String::with_capacity(*size)))
+		}
+	}
+}
+
+impl Node {
+	// Render element and subtree as HTML directly to w without buffering the
+	// whole output in memory. Wrap w in io::BufWriter, if it is unbuffered.
+	//
+	// Produces the same output as Node::html().
+	pub fn render_to<W: io::Write>(&mut self, w: &mut W) -> io::Result<()> {
+		let mut w = IoAdapter { w, err: None };
+		match self.write_html_to(&mut w) {
+			Ok(()) => Ok(()),
+			Err(e) => Err(w.err.unwrap_or_else(|| io::Error::other(e))),
+		}
+	}
+
+	// Return iterator over chunks of the HTML of the element and subtree.
+	// Each chunk, but the last one, is at least size bytes long.
+	//
+	// Produces the same output as Node::html() in total.
+	pub fn html_chunks(&mut self, size: usize) -> HtmlChunks<'_> {
+		HtmlChunks {
+			size,
+			buf: String::with_capacity(size),
+			root: self,
+			stack: vec![Frame { next: 0, tag: None }],
+		}
+	}
+
+	// Render element and subtree as HTML in chunks, passing each to sink and
+	// awaiting the returned future before rendering the next one. This allows
+	// streaming HTML to an asynchronous writer, like an HTTP response body,
+	// without buffering the whole output.
+	//
+	// Produces the same output as Node::html() in total.
+	pub async fn render_to_async<F, R>(&mut self, mut sink: F) -> io::Result<()>
+	where
+		F: FnMut(String) -> R,
+		R: Future<Output = io::Result<()>>,
+	{
+		for chunk in self.html_chunks(CHUNK_SIZE) {
+			sink(chunk).await?;
+		}
+		Ok(())
+	}
+}
+
+// Tree with many large text nodes
+#[cfg(test)]
+fn large_tree() -> Node {
+	let text = "x".repeat(100);
+	crate::element!("div", &[], (0..10).map(|_| {
+		crate::element!("ul", &[], (0..10).map(|_| {
+			crate::element!("li", &[], vec![crate::text!(text)])
+		}).collect())
+	}).collect())
+}
+
+#[test]
+fn render_to_writer() -> io::Result<()> {
+	let mut node = large_tree();
+	let mut out = Vec::new();
+	node.render_to(&mut out)?;
+	assert_eq!(String::from_utf8(out).unwrap(), node.html().unwrap());
+
+	// Errors of the writer are returned
+	struct Failing;
+	impl io::Write for Failing {
+		fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+			Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+		}
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+	assert_eq!(
+		node.render_to(&mut Failing).unwrap_err().kind(),
+		io::ErrorKind::BrokenPipe
+	);
+	Ok(())
+}
+
+#[test]
+fn render_chunks() {
+	let mut node = large_tree();
+	let html = node.html().unwrap();
+
+	let chunks: Vec<String> = node.html_chunks(1 << 10).collect();
+	assert!(chunks.len() > 10);
+	for c in chunks[..chunks.len() - 1].iter() {
+		assert!(c.len() >= 1 << 10);
+		assert!(c.len() < 2 << 10);
+	}
+	assert_eq!(chunks.concat(), html);
+
+	// Void elements and memos
+	let mut node = crate::element!("div", &[], vec![
+		crate::element!("br"),
+		Node::memo((), || crate::element!("p", &[], vec![crate::text!("a")])),
+	]);
+	assert_eq!(node.html_chunks(1).collect::<String>(), node.html().unwrap());
+}
+
+#[test]
+fn render_component_chunks() {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	#[derive(Default)]
+	struct Large(Rc<RefCell<Option<crate::Handle<Large>>>>);
+
+	impl crate::Component for Large {
+		fn render(&self) -> Node {
+			large_tree()
+		}
+
+		fn init(&mut self, handle: crate::Handle<Self>) {
+			*self.0.borrow_mut() = Some(handle);
+		}
+	}
+
+	let handle = Rc::new(RefCell::new(None));
+	let mut node = Node::component(Large(handle.clone()));
+	let html = node.html().unwrap();
+
+	// Component subtrees are not buffered whole
+	let mut chunks = Vec::new();
+	for c in node.html_chunks(1 << 10) {
+		chunks.push(c);
+
+		// Component is not borrowed between chunks
+		let handle = handle.borrow();
+		handle.as_ref().unwrap().set_state(|_| false).unwrap();
+	}
+	assert!(chunks.len() > 10);
+	assert!(chunks.iter().all(|c| c.len() < 2 << 10));
+	assert_eq!(chunks.concat(), html);
+
+	let mut node = crate::element!("div", &[], vec![
+		Node::memo((), || Node::component(Large::default())),
+		crate::element!("br"),
+	]);
+	let html = node.html().unwrap();
+	let chunks: Vec<String> = node.html_chunks(1 << 10).collect();
+	assert!(chunks.iter().all(|c| c.len() < 2 << 10));
+	assert_eq!(chunks.concat(), html);
+}
+
+#[test]
+fn render_async() {
+	use std::cell::RefCell;
+	use std::pin::pin;
+	use std::task::{Context, Poll, Waker};
+
+	let mut node = large_tree();
+	let html = node.html().unwrap();
+
+	// Sink, that is pending once per chunk
+	let out = RefCell::new(String::new());
+	let mut fut = pin!(node.render_to_async(|chunk| {
+		let out = &out;
+		let mut pending = true;
+		std::future::poll_fn(move |cx| {
+			if std::mem::take(&mut pending) {
+				cx.waker().wake_by_ref();
+				return Poll::Pending;
+			}
+			*out.borrow_mut() += &chunk;
+			Poll::Ready(Ok(()))
+		})
+	}));
+
+	let mut cx = Context::from_waker(Waker::noop());
+	let mut polls = 0;
+	while fut.as_mut().poll(&mut cx).is_pending() {
+		polls += 1;
+
+		// Chunks are produced lazily
+		assert!(out.borrow().len() < polls * CHUNK_SIZE * 2);
+	}
+	assert_eq!(polls, html.len().div_ceil(CHUNK_SIZE));
+	assert_eq!(*out.borrow(), html);
+}
@@ -0,0 +1,344 @@
+use super::error::Error;
+use super::node::Node;
+use super::util;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::{closure::Closure, JsCast};
+
+// Batches patches of dirty roots and components into one pass per animation
+// frame.
+//
+// Schedule root re-renders with schedule_root() and component state changes
+// with Handle::schedule(). Repeated scheduling of the same root or component
+// before the next frame is coalesced into a single patch.
+
+// Order in which scheduled patches are applied within a frame
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub enum Priority {
+	High,
+	#[default]
+	Normal,
+	Low,
+}
+
+// Callback to run on a frame
+pub type Frame = Box<dyn FnOnce()>;
+
+// Source of frames to run scheduled patches on.
+//
+// Defaults to window.requestAnimationFrame(). Replace with set_clock() to drive
+// the scheduler manually, for example in native unit tests.
+pub trait Clock {
+	// Call f on the next frame
+	fn request_frame(&self, f: Frame) -> Result<(), Error>;
+
+	// Return current monotonic time in milliseconds. Used for time budgets.
+	//
+	// Defaults to performance.now().
+	fn now(&self) -> f64 {
+		util::window()
+			.performance()
+			.expect("no performance on window")
+			.now()
+	}
+}
+
+// Clock using window.requestAnimationFrame()
+struct AnimationFrame;
+
+impl Clock for AnimationFrame {
+	fn request_frame(&self, f: Frame) -> Result<(), Error> {
+		util::window().request_animation_frame(
+			Closure::once_into_js(f).unchecked_ref(),
+		)?;
+		Ok(())
+	}
+}
+
+type Task = Box<dyn FnOnce() -> Result<(), Error>>;
+
+#[derive(Default)]
+struct Scheduler {
+	// Clock override. Uses AnimationFrame, if not set.
+	clock: Option<Rc<dyn Clock>>,
+
+	// A frame has been requested and not run yet
+	frame_requested: bool,
+
+	// Queued tasks with the address of their root or component, used for
+	// coalescing, in scheduling order
+	queue: Vec<(usize, Priority, Task)>,
+}
+
+thread_local! {
+	static SCHEDULER: RefCell<Scheduler> = RefCell::new(Default::default());
+}
+
+// Set the clock used to request frames
+pub fn set_clock<C: Clock + 'static>(clock: C) {
+	SCHEDULER.with(|s| s.borrow_mut().clock = Some(Rc::new(clock)));
+}
+
+// Return the clock in use
+fn clock() -> Rc<dyn Clock> {
+	SCHEDULER.with(|s| {
+		s.borrow()
+			.clock
+			.clone()
+			.unwrap_or_else(|| Rc::new(AnimationFrame))
+	})
+}
+
+// Return current time of the clock in use in milliseconds
+pub(crate) fn now() -> f64 {
+	clock().now()
+}
+
+// Queue task for the next frame. Replaces any task queued for the same id,
+// keeping the higher priority.
+pub(crate) fn enqueue(
+	id: usize,
+	priority: Priority,
+	task: Task,
+) -> Result<(), Error> {
+	let request = SCHEDULER.with(|s| {
+		let mut s = s.borrow_mut();
+		match s.queue.iter_mut().find(|(i, _, _)| *i == id) {
+			Some(queued) => {
+				queued.1 = queued.1.min(priority);
+				queued.2 = task;
+			}
+			None => s.queue.push((id, priority, task)),
+		}
+
+		if s.frame_requested {
+			false
+		} else {
+			s.frame_requested = true;
+			true
+		}
+	});
+
+	// Called outside the borrow, in case the clock runs f synchronously
+	if !request {
+		return Ok(());
+	}
+	let res = clock().request_frame(Box::new(|| {
+		if let Err(e) = run_frame() {
+			wasm_bindgen::throw_val(e.into());
+		}
+	}));
+	if res.is_err() {
+		// Request a frame again on the next call
+		SCHEDULER.with(|s| s.borrow_mut().frame_requested = false);
+	}
+	res
+}
+
+// Run frame requested by enqueue()
+fn run_frame() -> Result<(), Error> {
+	SCHEDULER.with(|s| s.borrow_mut().frame_requested = false);
+	flush_sync()
+}
+
+// Immediately apply all queued patches in priority order. Patches scheduled
+// while flushing are applied on the next frame.
+//
+// All patches are applied, even if some fail. Returns the first error.
+pub fn flush_sync() -> Result<(), Error> {
+	let mut queue =
+		SCHEDULER.with(|s| std::mem::take(&mut s.borrow_mut().queue));
+	queue.sort_by_key(|(_, p, _)| *p);
+
+	let mut res = Ok(());
+	for (_, _, task) in queue {
+		if let Err(e) = task() {
+			if res.is_ok() {
+				res = Err(e);
+			}
+		}
+	}
+	res
+}
+
+// Schedule root to be patched with the result of render on the next frame.
+// Only the last render scheduled before the frame is called.
+//
+// Does nothing on the frame, if root has been dropped by then.
+pub fn schedule_root<F>(
+	root: &Rc<RefCell<Node>>,
+	priority: Priority,
+	render: F,
+) -> Result<(), Error>
+where
+	F: FnOnce() -> Node + 'static,
+{
+	let weak = Rc::downgrade(root);
+	enqueue(
+		Rc::as_ptr(root) as usize,
+		priority,
+		Box::new(move || match weak.upgrade() {
+			Some(root) => root.borrow_mut().patch(render()),
+			None => Ok(()),
+		}),
+	)
+}
+
+// Clock, that runs frames only when ticked and has a constant time
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct ManualClock(Rc<RefCell<Vec<Frame>>>);
+
+#[cfg(test)]
+impl ManualClock {
+	// Return number of requested frames, that have not run yet
+	pub fn requested(&self) -> usize {
+		self.0.borrow().len()
+	}
+
+	// Run all requested frames
+	pub fn tick(&self) {
+		let frames = std::mem::take(&mut *self.0.borrow_mut());
+		for f in frames {
+			f();
+		}
+	}
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+	fn request_frame(&self, f: Frame) -> Result<(), Error> {
+		self.0.borrow_mut().push(f);
+		Ok(())
+	}
+
+	fn now(&self) -> f64 {
+		0.0
+	}
+}
+
+#[cfg(test)]
+fn text_node(text: &str) -> Node {
+	Node::text(&super::TextOptions {
+		text,
+		..Default::default()
+	})
+}
+
+#[cfg(test)]
+fn mounted_root(text: &str) -> Result<Rc<RefCell<Node>>, Error> {
+	let mut node = text_node(text);
+	node.html()?;
+	Ok(Rc::new(RefCell::new(node)))
+}
+
+#[test]
+fn coalesce_root_patches() -> Result<(), Error> {
+	let clock = ManualClock::default();
+	set_clock(clock.clone());
+	let root = mounted_root("text")?;
+	let renders = Rc::new(RefCell::new(Vec::new()));
+
+	for i in 0..3 {
+		let renders = renders.clone();
+		schedule_root(&root, Priority::Normal, move || {
+			renders.borrow_mut().push(i);
+			text_node("text")
+		})?;
+	}
+	assert_eq!(clock.requested(), 1);
+	assert!(renders.borrow().is_empty());
+
+	clock.tick();
+	assert_eq!(*renders.borrow(), [2]);
+	assert_eq!(clock.requested(), 0);
+
+	// Nothing left to flush
+	flush_sync()?;
+	assert_eq!(renders.borrow().len(), 1);
+	Ok(())
+}
+
+#[test]
+fn priority_order() -> Result<(), Error> {
+	let clock = ManualClock::default();
+	set_clock(clock.clone());
+	let order = Rc::new(RefCell::new(Vec::new()));
+	let schedule = |root: &Rc<RefCell<Node>>,
+	                priority: Priority,
+	                text: &'static str| {
+		let order = order.clone();
+		schedule_root(root, priority, move || {
+			order.borrow_mut().push(text);
+			text_node(text)
+		})
+	};
+
+	let roots = [
+		(mounted_root("low")?, Priority::Low, "low"),
+		(mounted_root("normal")?, Priority::Normal, "normal"),
+		(mounted_root("high")?, Priority::High, "high"),
+		(mounted_root("upgraded")?, Priority::Low, "upgraded"),
+	];
+	for (root, priority, text) in roots.iter() {
+		schedule(root, *priority, text)?;
+	}
+
+	// Rescheduling keeps the higher priority
+	schedule(&roots[3].0, Priority::High, "upgraded")?;
+
+	flush_sync()?;
+	assert_eq!(*order.borrow(), ["high", "upgraded", "normal", "low"]);
+
+	// Dropped roots are skipped
+	let root = mounted_root("dropped")?;
+	schedule(&root, Priority::High, "dropped")?;
+	drop(root);
+	clock.tick();
+	assert_eq!(order.borrow().len(), 4);
+	Ok(())
+}
+
+#[test]
+fn retry_failed_frame_request() -> Result<(), Error> {
+	use std::cell::Cell;
+
+	// Fails the first frame request
+	struct FlakyClock {
+		inner: ManualClock,
+		failed: Cell<bool>,
+	}
+
+	impl Clock for FlakyClock {
+		fn request_frame(&self, f: Frame) -> Result<(), Error> {
+			if !self.failed.replace(true) {
+				return Err(Error::Internal("frame request failed"));
+			}
+			self.inner.request_frame(f)
+		}
+	}
+
+	let clock = ManualClock::default();
+	set_clock(FlakyClock {
+		inner: clock.clone(),
+		failed: Cell::new(false),
+	});
+	let root = mounted_root("text")?;
+	let renders = Rc::new(Cell::new(0));
+	let schedule = || {
+		let renders = renders.clone();
+		schedule_root(&root, Priority::Normal, move || {
+			renders.set(renders.get() + 1);
+			text_node("text")
+		})
+	};
+
+	assert!(schedule().is_err());
+	assert_eq!(clock.requested(), 0);
+
+	schedule()?;
+	assert_eq!(clock.requested(), 1);
+	clock.tick();
+	assert_eq!(renders.get(), 1);
+	Ok(())
+}
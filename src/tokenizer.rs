@@ -2,310 +2,117 @@ use super::util;
 use std::cell::RefCell;
 use std::fmt;
 
+pub use super::attrs::register_tokenizable_values;
+
 /*
-Sorted list of predefined HTML tags and attributes to reduce allocations and
-need for map checks.
+Sorted and deduplicated list of predefined HTML tags and attributes to reduce
+allocations and need for map checks, a perfect hash table for looking up their
+tokens and the tag and attr modules with named constants for these tokens.
+
+Generated by build.rs from src/predefined/.
 
 Sourced from:
 https://developer.mozilla.org/en-US/docs/Web/HTML/Element
 https://developer.mozilla.org/en-US/docs/Web/HTML/Attributes
-
-NOTE: Some functions hard-code indexes into this. Do not change lightly.
-constexpr when?
 */
-static PREDEFINED: [&'static str; 285] = [
-	"a",
-	"abbr",
-	"accept",
-	"accept-charset",
-	"accesskey",
-	"acronym",
-	"action",
-	"address",
-	"align",
-	"allow",
-	"alt",
-	"applet",
-	"applet",
-	"area",
-	"article",
-	"aside",
-	"async",
-	"audio",
-	"autocapitalize",
-	"autocomplete",
-	"autofocus",
-	"autoplay",
-	"b",
-	"background",
-	"base",
-	"basefont",
-	"bdi",
-	"bdo",
-	"bgcolor",
-	"bgsound",
-	"big",
-	"blink",
-	"blockquote",
-	"body",
-	"border",
-	"br",
-	"buffered",
-	"button",
-	"canvas",
-	"caption",
-	"center",
-	"challenge",
-	"charset",
-	"checked",
-	"cite",
-	"cite",
-	"class",
-	"code",
-	"code",
-	"codebase",
-	"col",
-	"colgroup",
-	"color",
-	"cols",
-	"colspan",
-	"command",
-	"content",
-	"content",
-	"content",
-	"contenteditable",
-	"contextmenu",
-	"controls",
-	"coords",
-	"crossorigin",
-	"csp",
-	"data",
-	"data",
-	"data-*",
-	"datalist",
-	"datetime",
-	"dd",
-	"decoding",
-	"default",
-	"defer",
-	"del",
-	"details",
-	"dfn",
-	"dialog",
-	"dir",
-	"dir",
-	"dir",
-	"dirname",
-	"disabled",
-	"div",
-	"dl",
-	"download",
-	"draggable",
-	"dropzone",
-	"dt",
-	"element",
-	"element",
-	"em",
-	"embed",
-	"enctype",
-	"enterkeyhint",
-	"fieldset",
-	"figcaption",
-	"figure",
-	"font",
-	"footer",
-	"for",
-	"form",
-	"form",
-	"formaction",
-	"formenctype",
-	"formmethod",
-	"formnovalidate",
-	"formtarget",
-	"frame",
-	"frameset",
-	"h1",
-	"h2",
-	"h3",
-	"h4",
-	"h5",
-	"h6",
-	"head",
-	"header",
-	"headers",
-	"height",
-	"hgroup",
-	"hidden",
-	"high",
-	"hr",
-	"href",
-	"hreflang",
-	"html",
-	"http-equiv",
-	"i",
-	"icon",
-	"id",
-	"iframe",
-	"image",
-	"img",
-	"importance",
-	"input",
-	"inputmode",
-	"ins",
-	"integrity",
-	"intrinsicsize",
-	"isindex",
-	"ismap",
-	"itemprop",
-	"kbd",
-	"keygen",
-	"keytype",
-	"kind",
-	"label",
-	"label",
-	"lang",
-	"language",
-	"legend",
-	"li",
-	"link",
-	"list",
-	"listing",
-	"loading",
-	"loop",
-	"low",
-	"main",
-	"main",
-	"manifest",
-	"map",
-	"mark",
-	"marquee",
-	"max",
-	"maxlength",
-	"media",
-	"menu",
-	"menuitem",
-	"menuitem",
-	"meta",
-	"meter",
-	"method",
-	"min",
-	"minlength",
-	"multicol",
-	"multiple",
-	"muted",
-	"name",
-	"nav",
-	"nextid",
-	"nobr",
-	"noembed",
-	"noembed",
-	"noframes",
-	"noscript",
-	"novalidate",
-	"object",
-	"ol",
-	"open",
-	"optgroup",
-	"optimum",
-	"option",
-	"output",
-	"p",
-	"param",
-	"pattern",
-	"picture",
-	"ping",
-	"placeholder",
-	"plaintext",
-	"poster",
-	"pre",
-	"preload",
-	"progress",
-	"q",
-	"radiogroup",
-	"rb",
-	"readonly",
-	"referrerpolicy",
-	"rel",
-	"required",
-	"reversed",
-	"rows",
-	"rowspan",
-	"rp",
-	"rt",
-	"rtc",
-	"ruby",
-	"s",
-	"samp",
-	"sandbox",
-	"scope",
-	"scoped",
-	"script",
-	"section",
-	"select",
-	"selected",
-	"shadow",
-	"shadow",
-	"shape",
-	"size",
-	"sizes",
-	"slot",
-	"slot",
-	"small",
-	"source",
-	"spacer",
-	"span",
-	"span",
-	"spellcheck",
-	"src",
-	"srcdoc",
-	"srclang",
-	"srcset",
-	"start",
-	"step",
-	"strike",
-	"strong",
-	"style",
-	"style",
-	"sub",
-	"summary",
-	"summary",
-	"sup",
-	"tabindex",
-	"table",
-	"target",
-	"tbody",
-	"td",
-	"template",
-	"textarea",
-	"tfoot",
-	"th",
-	"thead",
-	"time",
-	"title",
-	"title",
-	"tr",
-	"track",
-	"translate",
-	"tt",
-	"tt",
-	"type",
-	"u",
-	"ul",
-	"usemap",
-	"value",
-	"var",
-	"video",
-	"wbr",
-	"width",
-	"wrap",
-	"xmp",
-];
+include!(concat!(env!("OUT_DIR"), "/predefined.rs"));
+include!("predefined/hash.rs");
+
+// Lookup token of a predefined string, if any.
+// Can be evaluated at compile time.
+#[doc(hidden)]
+#[inline]
+pub const fn lookup_predefined(s: &str) -> Option<u32> {
+	let (g, f1, f2) = phf_hash(PHF_SEED, s.as_bytes());
+	let (d1, d2) =
+		PHF_DISPLACEMENTS[g as usize % PHF_DISPLACEMENTS.len()];
+	let t = PHF_SLOTS[phf_slot(f1, f2, d1, d2, PHF_SLOTS.len())] as usize;
+	if t != 0 && const_str_eq(PREDEFINED[t - 1], s) {
+		Some(t as u32)
+	} else {
+		None
+	}
+}
+
+// Returns, if names sorted by their bytes are also sorted by their tokens.
+// Predefined tokens sort like their strings and before all other tokens, so
+// this holds, if all names but the last are predefined.
+// Can be evaluated at compile time.
+#[doc(hidden)]
+pub const fn in_token_order(names: &[&str]) -> bool {
+	let mut i = 0;
+	while i + 1 < names.len() {
+		if lookup_predefined(names[i]).is_none() {
+			return false;
+		}
+		i += 1;
+	}
+	true
+}
+
+// String equality comparison usable in const functions
+const fn const_str_eq(a: &str, b: &str) -> bool {
+	let (a, b) = (a.as_bytes(), b.as_bytes());
+	if a.len() != b.len() {
+		return false;
+	}
+	let mut i = 0;
+	while i < a.len() {
+		if a[i] != b[i] {
+			return false;
+		}
+		i += 1;
+	}
+	true
+}
 
 thread_local! {
 	static REGISTRY: RefCell<Registry> = RefCell::new(Registry::new());
+
+	// Reference count changes of dynamic tokens cloned or dropped, while the
+	// registry was borrowed, like inside get_value(). Applied on the next
+	// mutable access to the registry.
+	static DEFERRED: RefCell<Vec<(u32, bool)>> = Default::default();
 }
 
-// Storage for small (len <= 15) strings without allocating extra heap memory
+// Run f with mutable access to the registry, after applying any deferred
+// reference count changes
+fn with_registry_mut<F, R>(f: F) -> R
+where
+	F: FnOnce(&mut Registry) -> R,
+{
+	util::with_global_mut(&REGISTRY, |r| {
+		r.apply_deferred();
+		f(r)
+	})
+}
+
+// Increment or decrement the reference count of a dynamic token. Deferred, if
+// the registry is currently borrowed.
+fn update_refs(k: u32, increment: bool) {
+	// Registry might have already been destroyed on thread exit
+	let _ = REGISTRY.try_with(|r| match r.try_borrow_mut() {
+		Ok(mut r) => {
+			r.apply_deferred();
+			if increment {
+				r.increment(k);
+			} else {
+				r.decrement(k);
+			}
+		}
+		Err(_) => {
+			let _ = DEFERRED.try_with(|d| d.borrow_mut().push((k, increment)));
+		}
+	});
+}
+
+#[cfg(feature = "stats")]
+thread_local! {
+	static COUNTERS: RefCell<Counters> = RefCell::new(Default::default());
+}
+
+// Storage for small (len <= 15 bytes) strings without allocating extra heap
+// memory
 #[derive(Default, PartialEq, Eq, Hash, Clone)]
 struct ArrayString {
 	length: u8,
@@ -315,78 +122,338 @@ struct ArrayString {
 impl ArrayString {
 	fn new(s: &str) -> Self {
 		let mut arr: [u8; 15] = Default::default();
-		for (i, ch) in s.chars().enumerate() {
-			arr[i] = ch as u8;
-		}
+		arr[..s.len()].copy_from_slice(s.as_bytes());
 		Self {
 			length: s.len() as u8,
-			arr: arr,
+			arr,
 		}
 	}
 }
 
 impl AsRef<str> for ArrayString {
 	fn as_ref(&self) -> &str {
-		std::str::from_utf8(&self.arr[..self.length as usize]).unwrap()
+		// Always copied from a valid &str
+		std::str::from_utf8(&self.arr[..self.length as usize])
+			.expect("valid UTF-8")
 	}
 }
 
 impl util::WriteHTMLTo for String {
 	fn write_html_to<W: fmt::Write>(&mut self, w: &mut W) -> fmt::Result {
-		w.write_str(&self)
+		w.write_str(self)
+	}
+}
+
+// Interned string token.
+//
+// Pre-interning frequently used strings, like tags, class names and attribute
+// keys, as tokens and passing them to node construction options skips
+// tokenization of the strings on each render.
+//
+// Tokens of strings not in the predefined set are reference counted. Once no
+// tokens of a string exist, it can be removed from the registry with sweep().
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Token(u32);
+
+impl Token {
+	// Token of the empty string
+	pub const EMPTY: Token = Token(0);
+
+	// Create token of a predefined string.
+	// Panics, if the string is not in the built-in predefined set. When used to
+	// initialize a constant, this panic happens at compile time.
+	pub const fn predefined(s: &str) -> Self {
+		match lookup_predefined(s) {
+			Some(t) => Token(t),
+			None => panic!("not a predefined string"),
+		}
+	}
+
+	// Return the numeric ID of the token, for example to include it in a patch
+	// stream. IDs of strings not in the predefined set only match across
+	// registries, that imported the same snapshot. See export_snapshot().
+	#[inline]
+	pub fn id(&self) -> u32 {
+		self.0
+	}
+
+	// Create token from an ID returned by Token::id().
+	// Returns None, if no string with this ID is in the registry.
+	pub fn from_id(id: u32) -> Option<Self> {
+		if id <= PREDEFINED.len() as u32 {
+			return Some(Token(id));
+		}
+		with_registry_mut(|r| {
+			if r.contains(id) {
+				r.increment(id);
+				Some(Token(id))
+			} else {
+				None
+			}
+		})
+	}
+
+	// Token is allocated in the registry and is reference counted
+	#[inline]
+	fn is_dynamic(&self) -> bool {
+		self.0 > PREDEFINED.len() as u32
 	}
 }
 
-// Contains id->string and string->id mappings
+impl From<&str> for Token {
+	// Panics, if the registry has run out of tokens
+	#[inline]
+	fn from(s: &str) -> Self {
+		tokenize(s)
+	}
+}
+
+impl From<&String> for Token {
+	#[inline]
+	fn from(s: &String) -> Self {
+		tokenize(s)
+	}
+}
+
+impl fmt::Display for Token {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		get_value(self, |s| f.write_str(s))
+	}
+}
+
+// String or token accepted by node construction options. Passing a Token skips
+// tokenization of the string.
+#[derive(Debug, Clone)]
+pub enum StrOrToken<'a> {
+	Str(&'a str),
+	Token(Token),
+}
+
+impl<'a> StrOrToken<'a> {
+	// Convert to token, tokenizing the string, if needed
+	#[inline]
+	pub fn to_token(&self) -> Token {
+		match self {
+			StrOrToken::Str(s) => tokenize(s),
+			StrOrToken::Token(t) => t.clone(),
+		}
+	}
+}
+
+impl StrOrToken<'static> {
+	// Token of a predefined string or else the string itself. Used by html! to
+	// tokenize predefined names at compile time.
+	#[doc(hidden)]
+	pub const fn literal(s: &'static str) -> Self {
+		match lookup_predefined(s) {
+			Some(t) => StrOrToken::Token(Token(t)),
+			None => StrOrToken::Str(s),
+		}
+	}
+}
+
+impl<'a, T: AsRef<str> + ?Sized> From<&'a T> for StrOrToken<'a> {
+	#[inline]
+	fn from(s: &'a T) -> Self {
+		StrOrToken::Str(s.as_ref())
+	}
+}
+
+impl<'a> From<Token> for StrOrToken<'a> {
+	#[inline]
+	fn from(t: Token) -> Self {
+		StrOrToken::Token(t)
+	}
+}
+
+impl<'a> From<&'a Token> for StrOrToken<'a> {
+	#[inline]
+	fn from(t: &'a Token) -> Self {
+		StrOrToken::Token(t.clone())
+	}
+}
+
+impl Clone for Token {
+	#[inline]
+	fn clone(&self) -> Self {
+		if self.is_dynamic() {
+			update_refs(self.0, true);
+		}
+		Self(self.0)
+	}
+}
+
+impl Drop for Token {
+	#[inline]
+	fn drop(&mut self) {
+		if self.is_dynamic() {
+			update_refs(self.0, false);
+		}
+	}
+}
+
+// Statistics of a registry sweep
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SweepStats {
+	// Number of strings removed from the registry
+	pub reclaimed: usize,
+
+	// Number of strings remaining in the registry
+	pub remaining: usize,
+}
+
+// Contains id->string and string->id mappings.
+//
+// Each table allocates IDs from its own 31 bit counter. Tokens of the large
+// table are distinguished by the highest bit being flagged.
 #[derive(Default)]
 struct Registry {
-	id_gen: util::IDGenerator,
+	small_ids: util::IDGenerator,
+	large_ids: util::IDGenerator,
 	small: util::TokenMap<ArrayString>,
 	large: util::TokenMap<String>,
+
+	// Tokens, that have had their reference count drop to zero since the last
+	// sweep. Each token is queued at most once, but can have been referenced
+	// again since.
+	unreferenced: Vec<u32>,
+
+	// Strings registered with register_predefined(). Their tokens hold an
+	// extra reference and are thus never reclaimed.
+	registered: Registered,
+}
+
+// Perfect hash table of strings registered with register_predefined(). Looked
+// up before the registry maps, like the built-in predefined set.
+#[derive(Default)]
+struct Registered {
+	seed: u64,
+	displacements: Vec<(u32, u32)>,
+
+	// 1-based indexes into entries
+	slots: Vec<u32>,
+
+	// Registered strings and their tokens in registration order
+	entries: Vec<(Box<str>, u32)>,
+}
+
+impl Registered {
+	fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	fn iter(&self) -> impl Iterator<Item = &(Box<str>, u32)> {
+		self.entries.iter()
+	}
+
+	// Lookup token of a registered string
+	#[inline]
+	fn lookup(&self, s: &str) -> Option<u32> {
+		if self.entries.is_empty() {
+			return None;
+		}
+		let (g, f1, f2) = phf_hash(self.seed, s.as_bytes());
+		let (d1, d2) =
+			self.displacements[g as usize % self.displacements.len()];
+		match self.slots[phf_slot(f1, f2, d1, d2, self.slots.len())] {
+			0 => None,
+			i => {
+				let (v, t) = &self.entries[i as usize - 1];
+				if &**v == s {
+					Some(*t)
+				} else {
+					None
+				}
+			}
+		}
+	}
+
+	// Add newly registered strings and rebuild the hash table
+	fn extend(&mut self, entries: Vec<(Box<str>, u32)>) {
+		if entries.is_empty() {
+			return;
+		}
+		self.entries.extend(entries);
+		let keys: Vec<&str> = self.entries.iter().map(|(s, _)| &**s).collect();
+		let (seed, displacements, slots) = phf_build(&keys);
+		self.seed = seed;
+		self.displacements = displacements;
+		self.slots = slots;
+	}
 }
 
 impl Registry {
 	fn new() -> Self {
 		Self {
-			id_gen: util::IDGenerator::new(PREDEFINED.len() as u16),
+			small_ids: util::IDGenerator::new(PREDEFINED.len() as u32),
 			..Default::default()
 		}
 	}
 
-	// Convert string to token
-	fn tokenize(&mut self, s: &str) -> u16 {
-		match s.len() {
-			0 => 0, // Don't store empty strings
+	// Convert string to token and increment its reference count.
+	// Returns None, if the ID space of the target table has been exhausted.
+	fn tokenize(&mut self, s: &str) -> Option<u32> {
+		if let Some(t) = self.registered.lookup(s) {
+			record(Lookup::Predefined, s);
+			self.increment(t);
+			return Some(t);
+		}
+
+		let t = match s.len() {
+			0 => {
+				// Don't store empty strings
+				record(Lookup::Predefined, s);
+				return Some(0);
+			}
 			1..=15 => {
 				let v = ArrayString::new(s);
 				match self.small.get_token(&v) {
-					Some(t) => *t,
+					Some(t) => {
+						record(Lookup::Registry, s);
+						t
+					}
 					None => {
-						let t = self.id_gen.new_id(false);
+						let t = self.small_ids.new_id(false)?;
 						self.small.insert(t, v);
+						record(Lookup::Inserted, s);
 						t
 					}
 				}
 			}
 			_ => {
-				let v = String::from(s);
-				match self.large.get_token(&v) {
-					Some(t) => *t,
+				match self.large.get_token(s) {
+					Some(t) => {
+						record(Lookup::Registry, s);
+						t
+					}
 					None => {
-						let t = self.id_gen.new_id(true);
-						self.large.insert(t, v);
+						let t = self.large_ids.new_id(true)?;
+						self.large.insert(t, s.into());
+						record(Lookup::Inserted, s);
 						t
 					}
 				}
 			}
+		};
+		self.increment(t);
+		Some(t)
+	}
+
+	// Lookup token of string without inserting it into the registry or
+	// incrementing its reference count
+	fn lookup(&self, s: &str) -> Option<u32> {
+		match s.len() {
+			0 => Some(0),
+			1..=15 => self.small.get_token(&ArrayString::new(s)),
+			_ => self.large.get_token(s),
 		}
 	}
 
 	/// Lookup string by token
-	fn get_value(&self, k: u16) -> &str {
+	fn get_value(&self, k: u32) -> &str {
 		if k == 0 {
 			""
-		} else if k <= PREDEFINED.len() as u16 {
+		} else if k <= PREDEFINED.len() as u32 {
 			PREDEFINED[k as usize - 1]
 		} else if util::IDGenerator::is_flagged(k) {
 			self.large.get_value(k).as_ref()
@@ -394,21 +461,1050 @@ impl Registry {
 			self.small.get_value(k).as_ref()
 		}
 	}
+
+	// Returns, if the registry contains a string with the dynamic token
+	fn contains(&self, k: u32) -> bool {
+		if util::IDGenerator::is_flagged(k) {
+			self.large.refs(k).is_some()
+		} else {
+			self.small.refs(k).is_some()
+		}
+	}
+
+	// Increment reference count of dynamic token
+	fn increment(&mut self, k: u32) {
+		if util::IDGenerator::is_flagged(k) {
+			self.large.increment(k);
+		} else {
+			self.small.increment(k);
+		}
+	}
+
+	// Decrement reference count of dynamic token
+	fn decrement(&mut self, k: u32) {
+		let queue = if util::IDGenerator::is_flagged(k) {
+			self.large.decrement(k) == 0 && self.large.queue(k)
+		} else {
+			self.small.decrement(k) == 0 && self.small.queue(k)
+		};
+		if queue {
+			self.unreferenced.push(k);
+		}
+	}
+
+	// Apply reference count changes deferred by update_refs()
+	fn apply_deferred(&mut self) {
+		let _ = DEFERRED.try_with(|d| {
+			for (k, increment) in d.borrow_mut().drain(..) {
+				if increment {
+					self.increment(k);
+				} else {
+					self.decrement(k);
+				}
+			}
+		});
+	}
+
+	// Remove all unreferenced strings from the registry and release their
+	// tokens for reuse
+	fn sweep(&mut self) -> SweepStats {
+		let mut stats = SweepStats::default();
+		for k in std::mem::take(&mut self.unreferenced) {
+			let reclaimed = if util::IDGenerator::is_flagged(k) {
+				self.large.dequeue(k);
+				self.large.refs(k) == Some(0) && {
+					self.large.remove(k);
+					self.large_ids.release(k);
+					true
+				}
+			} else {
+				self.small.dequeue(k);
+				self.small.refs(k) == Some(0) && {
+					self.small.remove(k);
+					self.small_ids.release(k);
+					true
+				}
+			};
+			if reclaimed {
+				stats.reclaimed += 1;
+			}
+		}
+		stats.remaining = self.small.len() + self.large.len();
+		stats
+	}
+}
+
+// Convert string to token.
+//
+// Panics, if the registry has run out of tokens. Use try_tokenize() for values,
+// that can fall back to untokenized storage.
+#[inline]
+pub(crate) fn tokenize(s: &str) -> Token {
+	try_tokenize(s).expect("token registry exhausted")
 }
 
-// Convert string to token
+// Convert string to token.
+// Returns None, if the registry has run out of tokens.
 #[inline]
-pub fn tokenize(s: &str) -> u16 {
-	if let Ok(i) = PREDEFINED.binary_search(&s) {
-		return i as u16 + 1;
+pub(crate) fn try_tokenize(s: &str) -> Option<Token> {
+	if let Some(t) = lookup_predefined(s) {
+		record(Lookup::Predefined, s);
+		return Some(Token(t));
 	}
-	util::with_global_mut(&REGISTRY, |r| r.tokenize(s))
+	with_registry_mut(|r| r.tokenize(s)).map(Token)
 }
 
-// Lookup value by token and pass it to f
-pub fn get_value<F, R>(k: u16, f: F) -> R
+// Lookup value by token and pass it to f.
+//
+// Tokens can be cloned and dropped inside f, but no strings can be tokenized.
+pub(crate) fn get_value<F, R>(k: &Token, f: F) -> R
 where
 	F: FnOnce(&str) -> R,
 {
-	util::with_global(&REGISTRY, |r| f(r.get_value(k)))
+	util::with_global(&REGISTRY, |r| f(r.get_value(k.0)))
+}
+
+// Remove strings no longer referenced by any token from the registry and
+// release their tokens for reuse. Call this periodically, for example after
+// rendering a frame, to prevent unbounded growth of the registry in long
+// running applications.
+//
+// Strings are not removed as soon as they become unreferenced, because they are
+// very likely to be tokenized again by the next render.
+pub fn sweep() -> SweepStats {
+	with_registry_mut(|r| r.sweep())
+}
+
+// Result of a string tokenization
+#[cfg_attr(not(feature = "stats"), allow(dead_code))]
+enum Lookup {
+	// Found in the predefined set
+	Predefined,
+
+	// Found in the registry
+	Registry,
+
+	// Inserted into the registry
+	Inserted,
+}
+
+// Record tokenization result in the statistics counters. No-op, if the "stats"
+// feature is disabled.
+#[inline]
+#[cfg_attr(not(feature = "stats"), allow(unused_variables))]
+fn record(res: Lookup, s: &str) {
+	#[cfg(feature = "stats")]
+	util::with_global_mut(&COUNTERS, |c| {
+		match res {
+			Lookup::Predefined => c.predefined_hits += 1,
+			Lookup::Registry => c.registry_hits += 1,
+			Lookup::Inserted => {
+				c.insertions += 1;
+				return;
+			}
+		}
+		c.bytes_saved += s.len().saturating_sub(std::mem::size_of::<Token>())
+			as u64;
+	});
+}
+
+// Tokenization counters collected since the last reset_stats() call
+#[cfg(feature = "stats")]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+struct Counters {
+	predefined_hits: u64,
+	registry_hits: u64,
+	insertions: u64,
+	bytes_saved: u64,
+}
+
+// Tokenizer statistics. Requires the "stats" feature.
+#[cfg(feature = "stats")]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+	// Strings found in the predefined set
+	pub predefined_hits: u64,
+
+	// Strings found in the registry
+	pub registry_hits: u64,
+
+	// Strings inserted into the registry
+	pub insertions: u64,
+
+	// Number of strings in the registry table for strings of up to 15 bytes
+	pub small_table_size: usize,
+
+	// Number of strings in the registry table for strings longer than 15
+	// bytes
+	pub large_table_size: usize,
+
+	// Estimate of bytes saved by storing tokens instead of strings, that
+	// already had a token
+	pub bytes_saved: u64,
+}
+
+// String allocated a dynamic token in the registry. Requires the "stats"
+// feature.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynamicToken {
+	// Numeric ID of the token
+	pub id: u32,
+
+	// Tokenized string
+	pub value: String,
+
+	// Number of references to the token. Imported tokens hold an extra
+	// reference.
+	pub refs: usize,
+}
+
+// Return tokenizer statistics collected since the last reset_stats() call.
+// Requires the "stats" feature.
+#[cfg(feature = "stats")]
+pub fn stats() -> Stats {
+	let c = util::with_global(&COUNTERS, |c| *c);
+	let (small_table_size, large_table_size) =
+		util::with_global(&REGISTRY, |r| (r.small.len(), r.large.len()));
+	Stats {
+		predefined_hits: c.predefined_hits,
+		registry_hits: c.registry_hits,
+		insertions: c.insertions,
+		small_table_size,
+		large_table_size,
+		bytes_saved: c.bytes_saved,
+	}
+}
+
+// Reset tokenization counters. Requires the "stats" feature.
+#[cfg(feature = "stats")]
+pub fn reset_stats() {
+	util::with_global_mut(&COUNTERS, |c| *c = Default::default());
+}
+
+// Return all strings with dynamic tokens in the registry sorted by token ID.
+// Requires the "stats" feature.
+#[cfg(feature = "stats")]
+pub fn dump_tokens() -> Vec<DynamicToken> {
+	with_registry_mut(|r| {
+		let mut tokens: Vec<DynamicToken> = r
+			.small
+			.iter()
+			.map(|(id, v)| (id, v.as_ref(), r.small.refs(id)))
+			.chain(
+				r.large
+					.iter()
+					.map(|(id, v)| (id, v.as_ref(), r.large.refs(id))),
+			)
+			.map(|(id, value, refs)| DynamicToken {
+				id,
+				value: value.into(),
+				refs: refs.unwrap_or_default(),
+			})
+			.collect();
+		tokens.sort_unstable_by_key(|t| t.id);
+		tokens
+	})
+}
+
+// Version of the registry snapshot format
+const SNAPSHOT_VERSION: u8 = 1;
+
+// Error returned by token registry operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryError {
+	// Registry already contains dynamically allocated tokens, other than the
+	// ones registered with register_predefined()
+	NotEmpty,
+
+	// Registry has run out of tokens
+	Exhausted,
+
+	// Snapshot was created by an incompatible format version or with a
+	// different set of predefined tokens
+	Incompatible,
+
+	// Snapshot is truncated or otherwise malformed
+	Malformed,
+}
+
+// Former name of RegistryError
+#[deprecated(note = "renamed to RegistryError")]
+pub type ImportError = RegistryError;
+
+impl fmt::Display for RegistryError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			RegistryError::NotEmpty => "token registry not empty",
+			RegistryError::Exhausted => "token registry exhausted",
+			RegistryError::Incompatible => "incompatible registry snapshot",
+			RegistryError::Malformed => "malformed registry snapshot",
+		})
+	}
+}
+
+impl std::error::Error for RegistryError {}
+
+// Reads values from a registry snapshot
+struct SnapshotReader<'a>(&'a [u8]);
+
+impl<'a> SnapshotReader<'a> {
+	fn bytes(&mut self, n: usize) -> Result<&'a [u8], RegistryError> {
+		if self.0.len() < n {
+			return Err(RegistryError::Malformed);
+		}
+		let (b, rest) = self.0.split_at(n);
+		self.0 = rest;
+		Ok(b)
+	}
+
+	fn byte(&mut self) -> Result<u8, RegistryError> {
+		Ok(self.bytes(1)?[0])
+	}
+
+	// Read LEB128-encoded u32
+	fn varint(&mut self) -> Result<u32, RegistryError> {
+		let mut v = 0u64;
+		for shift in (0..35).step_by(7) {
+			let b = self.byte()?;
+			v |= ((b & 0x7f) as u64) << shift;
+			if b & 0x80 == 0 {
+				return if v <= u32::MAX as u64 {
+					Ok(v as u32)
+				} else {
+					Err(RegistryError::Malformed)
+				};
+			}
+		}
+		Err(RegistryError::Malformed)
+	}
+}
+
+// Write LEB128-encoded u32
+fn write_varint(w: &mut Vec<u8>, mut v: u32) {
+	while v >= 0x80 {
+		w.push(v as u8 | 0x80);
+		v >>= 7;
+	}
+	w.push(v as u8);
+}
+
+impl Registry {
+	// Registry contains no dynamically allocated tokens, other than the ones
+	// registered with register_predefined()
+	fn is_empty(&self) -> bool {
+		self.small.len() + self.large.len() == self.registered.len()
+	}
+
+	// Register strings as predefined for the lifetime of the registry
+	fn register(&mut self, strings: &[&str]) -> Result<(), RegistryError> {
+		if !self.is_empty() {
+			return Err(RegistryError::NotEmpty);
+		}
+		let mut added = Vec::with_capacity(strings.len());
+		let mut res = Ok(());
+		for s in strings {
+			if lookup_predefined(s).is_some() || self.lookup(s).is_some() {
+				continue;
+			}
+			match self.tokenize(s) {
+				Some(t) => added.push((Box::from(*s), t)),
+				None => {
+					res = Err(RegistryError::Exhausted);
+					break;
+				}
+			}
+		}
+		self.registered.extend(added);
+		res
+	}
+
+	// Encode registry state as a snapshot
+	fn export(&self) -> Vec<u8> {
+		fn write_table<T: Eq + std::hash::Hash + Clone + AsRef<str>>(
+			w: &mut Vec<u8>,
+			ids: &util::IDGenerator,
+			table: &util::TokenMap<T>,
+		) {
+			let (counter, released) = ids.state();
+			write_varint(w, counter);
+			write_varint(w, released.len() as u32);
+			for id in released {
+				write_varint(w, *id);
+			}
+
+			// Sort for deterministic output
+			let mut entries: Vec<(u32, &str)> =
+				table.iter().map(|(k, v)| (k, v.as_ref())).collect();
+			entries.sort_unstable();
+			write_varint(w, entries.len() as u32);
+			for (k, v) in entries {
+				write_varint(w, k & util::IDGenerator::MAX);
+				write_varint(w, v.len() as u32);
+				w.extend_from_slice(v.as_bytes());
+			}
+		}
+
+		let mut w = vec![SNAPSHOT_VERSION];
+		write_varint(&mut w, PREDEFINED.len() as u32);
+		write_table(&mut w, &self.small_ids, &self.small);
+		write_table(&mut w, &self.large_ids, &self.large);
+		w
+	}
+
+	// Decode registry from snapshot.
+	//
+	// Imported tokens are never reclaimed by sweep(), as they must stay in
+	// agreement with the registry, that created the snapshot.
+	fn import(buf: &[u8]) -> Result<Self, RegistryError> {
+		// ID generator and entries of a table
+		type Table<'a> = (util::IDGenerator, Vec<(u32, &'a str)>);
+
+		// Read and validate table state
+		fn read_table<'a>(
+			r: &mut SnapshotReader<'a>,
+			start_from: u32,
+			small: bool,
+		) -> Result<Table<'a>, RegistryError> {
+			let counter = r.varint()?;
+			if counter < start_from || counter > util::IDGenerator::MAX {
+				return Err(RegistryError::Malformed);
+			}
+			let valid_id = |id: u32| id > start_from && id <= counter;
+
+			let mut released = Vec::new();
+			for _ in 0..r.varint()? {
+				let id = r.varint()?;
+				if !valid_id(id) {
+					return Err(RegistryError::Malformed);
+				}
+				released.push(id);
+			}
+
+			let mut entries = Vec::new();
+			for _ in 0..r.varint()? {
+				let id = r.varint()?;
+				let len = r.varint()? as usize;
+				if !valid_id(id)
+					|| released.contains(&id)
+					|| small != (len <= 15)
+					|| len == 0
+				{
+					return Err(RegistryError::Malformed);
+				}
+				let s = std::str::from_utf8(r.bytes(len)?)
+					.map_err(|_| RegistryError::Malformed)?;
+				entries.push((id, s));
+			}
+
+			Ok((util::IDGenerator::from_state(counter, released), entries))
+		}
+
+		let mut r = SnapshotReader(buf);
+		if r.byte()? != SNAPSHOT_VERSION
+			|| r.varint()? != PREDEFINED.len() as u32
+		{
+			return Err(RegistryError::Incompatible);
+		}
+
+		let (small_ids, small) =
+			read_table(&mut r, PREDEFINED.len() as u32, true)?;
+		let (large_ids, large) = read_table(&mut r, 0, false)?;
+		if !r.0.is_empty() {
+			return Err(RegistryError::Malformed);
+		}
+
+		let mut reg = Self {
+			small_ids,
+			large_ids,
+			..Default::default()
+		};
+		for (id, s) in small {
+			let v = ArrayString::new(s);
+			if reg.small.get_token(&v).is_some() || reg.small.refs(id).is_some()
+			{
+				return Err(RegistryError::Malformed);
+			}
+			reg.small.insert(id, v);
+			reg.small.increment(id);
+		}
+		for (id, s) in large {
+			let id = id | !util::IDGenerator::MAX;
+			let v = String::from(s);
+			if reg.large.get_token(&v).is_some() || reg.large.refs(id).is_some()
+			{
+				return Err(RegistryError::Malformed);
+			}
+			reg.large.insert(id, v);
+			reg.large.increment(id);
+		}
+		Ok(reg)
+	}
+}
+
+// Export the state of the token registry as a compact binary snapshot.
+//
+// Embedding the snapshot into server side rendered output and importing it on
+// the client with import_snapshot() before any rendering ensures tokens agree
+// across both.
+pub fn export_snapshot() -> Vec<u8> {
+	util::with_global(&REGISTRY, |r| r.export())
+}
+
+// Import token registry state from a snapshot created by export_snapshot().
+// Must be called before any strings not in the predefined set have been
+// tokenized. Strings registered with register_predefined() must be registered
+// in the same order before creating the snapshot.
+//
+// Imported strings are never reclaimed by sweep().
+pub fn import_snapshot(buf: &[u8]) -> Result<(), RegistryError> {
+	with_registry_mut(|r| {
+		if !r.is_empty() {
+			return Err(RegistryError::NotEmpty);
+		}
+		let mut imported = Registry::import(buf)?;
+		for (s, id) in r.registered.iter() {
+			if imported.lookup(s) != Some(*id) {
+				return Err(RegistryError::Incompatible);
+			}
+		}
+		imported.registered = std::mem::take(&mut r.registered);
+		*r = imported;
+		Ok(())
+	})
+}
+
+// Register strings as predefined, in addition to the built-in set of HTML tags
+// and attributes. Use this for custom element tags, attribute keys and
+// frequently used attribute values specific to the application.
+//
+// Registered strings are never reclaimed by sweep(). Must be called at startup
+// before any other strings not in the predefined set have been tokenized. The
+// tokens of the registered strings are stable across runs, as long as the
+// same strings are registered in the same order.
+pub fn register_predefined(strings: &[&str]) -> Result<(), RegistryError> {
+	with_registry_mut(|r| r.register(strings))
+}
+
+#[cfg(test)]
+fn registry_near_limit() -> Registry {
+	Registry {
+		small_ids: util::IDGenerator::new(util::IDGenerator::MAX - 2),
+		large_ids: util::IDGenerator::new(util::IDGenerator::MAX - 2),
+		..Default::default()
+	}
+}
+
+#[test]
+fn tables_have_separate_id_spaces() {
+	let mut r = Registry::new();
+	let small = r.tokenize("small").unwrap();
+	let large = r.tokenize("a string longer than 15 bytes").unwrap();
+	assert_eq!(small, PREDEFINED.len() as u32 + 1);
+	assert_eq!(large, 1 | 1 << 31);
+	assert_eq!(r.get_value(small), "small");
+	assert_eq!(r.get_value(large), "a string longer than 15 bytes");
+}
+
+#[test]
+fn small_table_exhaustion() {
+	let mut r = registry_near_limit();
+	let a = r.tokenize("a1").unwrap();
+	let b = r.tokenize("b1").unwrap();
+	assert_eq!(r.tokenize("c1"), None);
+	assert_eq!(r.tokenize("c2"), None);
+
+	// Existing tokens are still resolved and never overlap the flag bit
+	assert_eq!(r.tokenize("a1"), Some(a));
+	assert_eq!(r.tokenize("b1"), Some(b));
+	assert!(!util::IDGenerator::is_flagged(a));
+	assert!(!util::IDGenerator::is_flagged(b));
+	assert_eq!(r.get_value(a), "a1");
+	assert_eq!(r.get_value(b), "b1");
+
+	// The large table is unaffected
+	assert!(r.tokenize("a string longer than 15 bytes").is_some());
+}
+
+#[test]
+fn large_table_exhaustion() {
+	let mut r = registry_near_limit();
+	let a = r.tokenize("aaaaaaaaaaaaaaaaaaaa").unwrap();
+	let b = r.tokenize("bbbbbbbbbbbbbbbbbbbb").unwrap();
+	assert_eq!(r.tokenize("cccccccccccccccccccc"), None);
+	assert_eq!(a, (util::IDGenerator::MAX - 1) | 1 << 31);
+	assert_eq!(b, util::IDGenerator::MAX | 1 << 31);
+	assert_eq!(r.get_value(a), "aaaaaaaaaaaaaaaaaaaa");
+	assert_eq!(r.get_value(b), "bbbbbbbbbbbbbbbbbbbb");
+
+	// The small table is unaffected
+	assert!(r.tokenize("small").is_some());
+}
+
+#[test]
+fn many_small_tokens() {
+	let mut r = Registry::new();
+	let tokens: Vec<u32> = (0..40_000)
+		.map(|i| r.tokenize(&i.to_string()).unwrap())
+		.collect();
+	for (i, t) in tokens.iter().enumerate() {
+		assert!(!util::IDGenerator::is_flagged(*t));
+		assert_eq!(r.get_value(*t), i.to_string());
+	}
+}
+
+#[test]
+fn multibyte_small_strings() {
+	for s in &[
+		"привет",
+		"класс-1",
+		"漢字",
+		"中文类名",
+		"🦀",
+		"🦀🦀🦀",
+		"é",
+		"ab🦀cdefghijk", // Exactly 15 bytes
+	] {
+		assert!(s.len() <= 15);
+		let t = tokenize(s);
+		assert_eq!(tokenize(s), t);
+		get_value(&t, |v| assert_eq!(v, *s));
+	}
+}
+
+#[test]
+fn multibyte_strings_do_not_collide() {
+	// Would map to the same bytes, if code points were truncated to u8
+	let a = tokenize("Ā");
+	let b = tokenize("Ȁ");
+	assert_ne!(a, b);
+	get_value(&a, |v| assert_eq!(v, "Ā"));
+	get_value(&b, |v| assert_eq!(v, "Ȁ"));
+}
+
+#[test]
+fn multibyte_large_strings() {
+	let s = "очень-длинное-имя-класса";
+	let t = tokenize(s);
+	assert!(util::IDGenerator::is_flagged(t.0));
+	get_value(&t, |v| assert_eq!(v, s));
+}
+
+#[test]
+fn predefined_sorted_and_unique() {
+	for w in PREDEFINED.windows(2) {
+		assert!(w[0] < w[1], "{:?} >= {:?}", w[0], w[1]);
+	}
+}
+
+#[test]
+fn predefined_constants() {
+	for (t, s) in &[
+		(tag::A, "a"),
+		(tag::BR, "br"),
+		(tag::DIV, "div"),
+		(tag::HR, "hr"),
+		(tag::WBR, "wbr"),
+		(tag::XMP, "xmp"),
+		(attr::ACCEPT_CHARSET, "accept-charset"),
+		(attr::CLASS, "class"),
+		(attr::HTTP_EQUIV, "http-equiv"),
+		(attr::WRAP, "wrap"),
+	] {
+		assert_eq!(tokenize(s), *t);
+		get_value(t, |v| assert_eq!(v, *s));
+	}
+
+	// Strings that are both tags and attributes share a token
+	assert_eq!(tag::SPAN, attr::SPAN);
+	assert_eq!(tag::TITLE, attr::TITLE);
+}
+
+#[test]
+fn perfect_hash_lookup() {
+	for (i, s) in PREDEFINED.iter().enumerate() {
+		assert_eq!(lookup_predefined(s), Some(i as u32 + 1));
+	}
+	for s in &["", "x", "divv", "dvi", "data-*", "aria-label", "🦀"] {
+		assert_eq!(lookup_predefined(s), None);
+	}
+}
+
+#[test]
+fn const_perfect_hash_lookup() {
+	const DIV: Option<u32> = lookup_predefined("div");
+	const NONE: Option<u32> = lookup_predefined("not-predefined");
+	assert_eq!(DIV, Some(tag::DIV.0));
+	assert_eq!(NONE, None);
+}
+
+#[test]
+fn names_in_token_order() {
+	const ORDERED: [bool; 4] = [
+		in_token_order(&["class", "href", "my-attr"]),
+		in_token_order(&[]),
+		in_token_order(&["my-attr"]),
+		in_token_order(&["data-a", "title"]),
+	];
+	assert_eq!(ORDERED, [true, true, true, false]);
+}
+
+#[test]
+fn sweep_unreferenced() {
+	let a = tokenize("sweep-a");
+	let b = tokenize("a large string to be swept away");
+	let a_id = a.0;
+	let b_id = b.0;
+	let kept = tokenize("sweep-kept");
+
+	// Referenced tokens are not reclaimed
+	assert_eq!(sweep().reclaimed, 0);
+
+	drop(a);
+	drop(b);
+	let stats = sweep();
+	assert_eq!(stats.reclaimed, 2);
+	assert_eq!(stats.remaining, 1);
+	get_value(&kept, |v| assert_eq!(v, "sweep-kept"));
+
+	// Released IDs are reused
+	assert_eq!(tokenize("sweep-c").0, a_id);
+	assert_eq!(tokenize("another large string to be swept").0, b_id);
+}
+
+#[test]
+fn sweep_reference_counting() {
+	let a = tokenize("counted");
+	let b = a.clone();
+	let c = tokenize("counted");
+	assert_eq!(a, c);
+
+	drop(a);
+	drop(c);
+	assert_eq!(sweep().reclaimed, 0);
+	get_value(&b, |v| assert_eq!(v, "counted"));
+
+	drop(b);
+	assert_eq!(sweep().reclaimed, 1);
+}
+
+#[test]
+fn clone_and_drop_while_borrowed() {
+	let a = tokenize("borrowed");
+	let b = get_value(&a, |v| {
+		assert_eq!(v, "borrowed");
+		let b = a.clone();
+		drop(a.clone());
+		b
+	});
+	drop(a);
+	assert_eq!(sweep().reclaimed, 0);
+
+	get_value(&b, |_| drop(b.clone()));
+	let c = b.clone();
+	get_value(&c, |_| drop(b));
+	assert_eq!(sweep().reclaimed, 0);
+	drop(c);
+	assert_eq!(sweep().reclaimed, 1);
+}
+
+#[test]
+fn sweep_retokenized() {
+	// Tokenized again after becoming unreferenced, but before the sweep
+	let a = tokenize("retokenized");
+	let id = a.0;
+	drop(a);
+	let a = tokenize("retokenized");
+	assert_eq!(a.0, id);
+	assert_eq!(sweep().reclaimed, 0);
+	drop(a);
+	assert_eq!(sweep().reclaimed, 1);
+	assert_eq!(sweep().reclaimed, 0);
+}
+
+#[test]
+fn unreferenced_queued_once() {
+	let unreferenced = || util::with_global(&REGISTRY, |r| r.unreferenced.len());
+
+	// Dropped and tokenized again many times without sweeping
+	for _ in 0..1000 {
+		drop(tokenize("toggled"));
+	}
+	assert!(unreferenced() <= 1);
+
+	assert_eq!(sweep().reclaimed, 1);
+	assert_eq!(unreferenced(), 0);
+	drop(tokenize("toggled"));
+	assert_eq!(unreferenced(), 1);
+}
+
+#[test]
+fn sweep_ignores_predefined() {
+	drop(tokenize("div"));
+	drop(tag::SPAN);
+	drop(Token::EMPTY);
+	assert_eq!(sweep(), SweepStats::default());
+}
+
+#[test]
+fn snapshot_round_trip() {
+	let tokens: Vec<(Token, &str)> = [
+		"server-a",
+		"a large server side string",
+		"server-b",
+		"сервер",
+		"a second large server side string",
+	]
+	.iter()
+	.map(|s| (tokenize(s), *s))
+	.collect();
+
+	// Released IDs must also be preserved
+	drop(tokenize("released"));
+	sweep();
+
+	let snapshot = export_snapshot();
+	let ids: Vec<(u32, &str)> = tokens.iter().map(|(t, s)| (t.0, *s)).collect();
+	std::thread::spawn(move || {
+		import_snapshot(&snapshot).unwrap();
+		for (id, s) in ids {
+			let t = tokenize(s);
+			assert_eq!(t.0, id);
+			get_value(&t, |v| assert_eq!(v, s));
+		}
+
+		// Imported tokens are pinned
+		assert_eq!(sweep().reclaimed, 0);
+
+		// Same allocation state
+		assert_eq!(export_snapshot(), snapshot);
+	})
+	.join()
+	.unwrap();
+}
+
+#[test]
+fn snapshot_import_into_non_empty() {
+	let snapshot = export_snapshot();
+	let _t = tokenize("not-empty");
+	assert_eq!(import_snapshot(&snapshot), Err(RegistryError::NotEmpty));
+}
+
+#[test]
+fn snapshot_import_invalid() {
+	let t = tokenize("valid");
+	let snapshot = export_snapshot();
+	drop(t);
+	sweep();
+
+	let mut incompatible = snapshot.clone();
+	incompatible[0] += 1;
+	assert_eq!(
+		Registry::import(&incompatible).err(),
+		Some(RegistryError::Incompatible)
+	);
+
+	for i in 0..snapshot.len() {
+		assert!(Registry::import(&snapshot[..i]).is_err());
+	}
+
+	let mut trailing = snapshot.clone();
+	trailing.push(0);
+	assert_eq!(
+		Registry::import(&trailing).err(),
+		Some(RegistryError::Malformed)
+	);
+
+	let mut invalid_utf8 = snapshot;
+	*invalid_utf8.last_mut().unwrap() = 0xff;
+	assert_eq!(
+		Registry::import(&invalid_utf8).err(),
+		Some(RegistryError::Malformed)
+	);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn collect_stats() {
+	reset_stats();
+	let _a = tokenize("div");
+	let _b = tokenize("");
+	let _c = tokenize("stats-small");
+	let _d = tokenize("stats-small");
+	let _e = tokenize("a large string for statistics");
+	let _f = tokenize("a large string for statistics");
+	assert_eq!(
+		stats(),
+		Stats {
+			predefined_hits: 2,
+			registry_hits: 2,
+			insertions: 2,
+			small_table_size: 1,
+			large_table_size: 1,
+			bytes_saved: "stats-small".len() as u64 - 4
+				+ "a large string for statistics".len() as u64
+				- 4,
+		}
+	);
+
+	reset_stats();
+	assert_eq!(
+		stats(),
+		Stats {
+			small_table_size: 1,
+			large_table_size: 1,
+			..Default::default()
+		}
+	);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn dump_dynamic_tokens() {
+	let a = tokenize("dump-a");
+	let _b = a.clone();
+	let c = tokenize("a large string to be dumped");
+	assert_eq!(
+		dump_tokens(),
+		vec![
+			DynamicToken {
+				id: a.0,
+				value: "dump-a".into(),
+				refs: 2,
+			},
+			DynamicToken {
+				id: c.0,
+				value: "a large string to be dumped".into(),
+				refs: 1,
+			},
+		]
+	);
+}
+
+#[test]
+fn register_predefined_strings() {
+	let strings = ["my-element", "div", "", "a registered large string"];
+	register_predefined(&strings).unwrap();
+	register_predefined(&["my-element", "my-other-elem"]).unwrap();
+
+	let start = PREDEFINED.len() as u32;
+	assert_eq!(tokenize("my-element").0, start + 1);
+	assert_eq!(tokenize("my-other-elem").0, start + 2);
+	assert_eq!(tokenize("a registered large string").0, 1 | 1 << 31);
+	assert_eq!(tokenize("div"), tag::DIV);
+	assert_eq!(sweep().reclaimed, 0);
+
+	drop(tokenize("unregistered"));
+	assert_eq!(
+		register_predefined(&["too-late"]),
+		Err(RegistryError::NotEmpty)
+	);
+}
+
+#[test]
+fn registered_lookup() {
+	let strings: Vec<String> = (0..100).map(|i| format!("reg-{}", i)).collect();
+	let mut r = Registry::new();
+	r.register(&strings.iter().map(|s| s.as_str()).collect::<Vec<_>>())
+		.unwrap();
+	r.register(&["reg-last", "reg-0"]).unwrap();
+	assert_eq!(r.registered.len(), 101);
+
+	for (s, t) in r.registered.iter() {
+		assert_eq!(r.registered.lookup(s), Some(*t));
+		assert_eq!(r.get_value(*t), &**s);
+	}
+	assert_eq!(r.registered.lookup("reg-100"), None);
+	assert_eq!(r.registered.lookup("div"), None);
+	assert_eq!(r.registered.lookup(""), None);
+}
+
+#[test]
+fn snapshot_with_registered() {
+	let snapshot = std::thread::spawn(|| {
+		register_predefined(&["registered"]).unwrap();
+		let _t = tokenize("dynamic");
+		export_snapshot()
+	})
+	.join()
+	.unwrap();
+
+	// Registered strings must match
+	std::thread::spawn({
+		let snapshot = snapshot.clone();
+		move || {
+			register_predefined(&["other"]).unwrap();
+			assert_eq!(
+				import_snapshot(&snapshot),
+				Err(RegistryError::Incompatible)
+			);
+		}
+	})
+	.join()
+	.unwrap();
+
+	register_predefined(&["registered"]).unwrap();
+	import_snapshot(&snapshot).unwrap();
+	assert_eq!(tokenize("registered").0, PREDEFINED.len() as u32 + 1);
+	assert_eq!(tokenize("dynamic").0, PREDEFINED.len() as u32 + 2);
+
+	assert_eq!(
+		register_predefined(&["registered-after"]),
+		Err(RegistryError::NotEmpty)
+	);
+}
+
+#[test]
+fn public_token() {
+	const DIV: Token = Token::predefined("div");
+	assert_eq!(DIV, tag::DIV);
+	assert_eq!(Token::from("div"), DIV);
+	assert_eq!(DIV.to_string(), "div");
+
+	let t = Token::from("my-class");
+	assert_eq!(Token::from(&String::from("my-class")), t);
+	assert_eq!(t.to_string(), "my-class");
+	assert_eq!(Token::EMPTY.to_string(), "");
+
+	let mut set = std::collections::HashSet::new();
+	set.insert(t.clone());
+	assert!(set.contains(&Token::from("my-class")));
+}
+
+#[test]
+#[should_panic(expected = "not a predefined string")]
+fn predefined_token_of_dynamic_string() {
+	Token::predefined("not-predefined");
+}
+
+#[test]
+fn token_ids() {
+	assert_eq!(Token::from_id(tag::DIV.id()), Some(tag::DIV));
+	assert_eq!(Token::from_id(0), Some(Token::EMPTY));
+
+	for s in ["small id", "a string longer than 15 bytes"].iter() {
+		let t = tokenize(s);
+		let id = t.id();
+		let copy = Token::from_id(id).unwrap();
+		assert_eq!(copy, t);
+
+		// The copy holds its own reference
+		drop(t);
+		sweep();
+		get_value(&copy, |v| assert_eq!(v, *s));
+
+		drop(copy);
+		sweep();
+		assert_eq!(Token::from_id(id), None);
+	}
+}
+
+#[test]
+fn literal_names() {
+	const DIV: StrOrToken<'static> = StrOrToken::literal("div");
+	assert!(matches!(DIV, StrOrToken::Token(t) if t == tag::DIV));
+	assert!(matches!(
+		StrOrToken::literal("my-element"),
+		StrOrToken::Str("my-element")
+	));
 }
@@ -1,70 +1,267 @@
 use std::borrow::{Borrow, BorrowMut};
+use std::cell::RefCell;
 use std::collections::HashMap;
 
 use std::fmt;
-use std::fmt::Display;
 use std::hash::Hash;
-use wasm_bindgen::JsValue;
-use web_sys;
+use super::error::Error;
 
-// Efficient append-only string builder for reducing reallocations
+// Append-only string builder, that writes into a list of chunks instead of
+// reallocating and copying a single growing buffer.
+//
+// Every chunk but the last one is filled to capacity. Allocated chunks are
+// kept for reuse after clear().
 pub struct Appender {
-	i: usize,
-	buffers: Vec<String>,
+	// Chunk being written to
+	buf: String,
+
+	// Filled chunks preceding buf
+	full: Vec<String>,
+
+	// Empty chunks to continue writing into, once buf is filled. Popped
+	// in the order they were originally filled in.
+	spare: Vec<String>,
+
+	// Buffer for concatenating chunks in join()
+	joined: String,
+}
+
+impl Default for Appender {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 impl Appender {
+	// Capacity of the first chunk. Each newly allocated chunk doubles the
+	// capacity of the previous one up to MAX_CHUNK.
+	const FIRST_CHUNK: usize = 256;
+
+	// Keeps chunks small enough to be reused by the allocator, instead of
+	// mapping fresh memory for each
+	const MAX_CHUNK: usize = 64 << 10;
+
 	pub fn new() -> Self {
-		return Appender {
-			i: 0,
-			buffers: vec![String::with_capacity(64)],
-		};
+		Appender {
+			buf: String::with_capacity(Self::FIRST_CHUNK),
+			full: Vec::new(),
+			spare: Vec::new(),
+			joined: String::new(),
+		}
 	}
 
-	fn current(&mut self) -> &mut String {
-		&mut self.buffers[self.i]
+	// Return total length of the written contents in bytes
+	pub fn len(&self) -> usize {
+		self.full.iter().map(String::len).sum::<usize>() + self.buf.len()
 	}
 
-	fn assert_cap(&mut self, append_size: usize) {
-		let buf = self.current();
-		let cap = buf.capacity();
-		if buf.len() + append_size > cap {
-			if self.i == self.buffers.len() - 1 {
-				self.buffers.push(String::with_capacity(cap * 2));
-			} else {
-				self.i += 1;
-			}
-		}
+	pub fn is_empty(&self) -> bool {
+		self.chunks().next().is_none()
+	}
+
+	// Iterate over the written contents in order as non-empty string slices.
+	// Allows passing the contents on without concatenating them first, like
+	// with vectored writes or by joining a js_sys::Array.
+	pub fn chunks(&self) -> impl Iterator<Item = &str> {
+		self.full
+			.iter()
+			.chain(std::iter::once(&self.buf))
+			.map(String::as_str)
+			.filter(|s| !s.is_empty())
 	}
 
 	// Clear all contents, but keep allocated memory for reuse
 	pub fn clear(&mut self) {
-		self.i = 0;
-		for b in self.buffers.iter_mut() {
-			b.clear()
+		self.buf.clear();
+		if self.full.is_empty() {
+			return;
+		}
+
+		// Restart from the first chunk and push the rest onto the spare stack
+		// in reverse, so they are reused in the same order
+		let last = std::mem::take(&mut self.buf);
+		self.full.push(last);
+		let mut it = self.full.drain(..);
+		self.buf = it.next().unwrap();
+		self.buf.clear();
+		for mut c in it.rev() {
+			c.clear();
+			self.spare.push(c);
+		}
+	}
+
+	// Append all contents to dst, reusing its capacity
+	pub fn dump_into(&self, dst: &mut String) {
+		dst.reserve(self.len());
+		for c in self.chunks() {
+			*dst += c;
+		}
+	}
+
+	// Return all contents as a single string slice. Concatenates chunks into
+	// an internal buffer, that is retained for reuse, if there is more than
+	// one.
+	pub fn join(&mut self) -> &str {
+		if self.full.is_empty() {
+			return &self.buf;
+		}
+		self.joined.clear();
+		self.joined.reserve(self.len());
+		for c in self.full.iter().chain(std::iter::once(&self.buf)) {
+			self.joined += c;
+		}
+		&self.joined
+	}
+
+	// Return total allocated capacity in bytes
+	fn capacity(&self) -> usize {
+		self.full
+			.iter()
+			.chain(self.spare.iter())
+			.map(String::capacity)
+			.sum::<usize>()
+			+ self.buf.capacity()
+			+ self.joined.capacity()
+	}
+
+	// Concatenate all contents into a new string
+	pub fn dump(&self) -> String {
+		let mut s = String::with_capacity(self.len());
+		self.dump_into(&mut s);
+		s
+	}
+
+	// Convert into a string. Does not copy the contents, if they fit into a
+	// single chunk.
+	pub fn into_string(self) -> String {
+		if self.full.is_empty() {
+			self.buf
+		} else {
+			self.dump()
 		}
 	}
 
-	// Dump all partial buffers into whole string
-	pub fn dump(&mut self) -> String {
-		self.buffers.concat()
+	// Return all contents as a string and clear them. Moves the chunk out
+	// instead of copying, if the contents fit into a single one.
+	pub fn take_string(&mut self) -> String {
+		if self.full.is_empty() {
+			std::mem::take(&mut self.buf)
+		} else {
+			let s = self.dump();
+			self.clear();
+			s
+		}
+	}
+
+	// Write s, that does not fit into the rest of buf, filling buf up to a
+	// character boundary and continuing with the next chunks
+	#[cold]
+	#[inline(never)]
+	fn write_overflowing(&mut self, mut s: &str) {
+		// buf has been moved out by take_string()
+		if self.buf.capacity() == 0 {
+			self.buf = self.spare.pop().unwrap_or_else(|| {
+				String::with_capacity(Self::FIRST_CHUNK)
+			});
+		}
+		loop {
+			let mut split = self.buf.capacity() - self.buf.len();
+			if split >= s.len() {
+				self.buf += s;
+				return;
+			}
+			while !s.is_char_boundary(split) {
+				split -= 1;
+			}
+			self.buf += &s[..split];
+			s = &s[split..];
+
+			let next = match self.spare.pop() {
+				Some(c) => c,
+				None => String::with_capacity(
+					(self.buf.capacity() * 2).min(Self::MAX_CHUNK).max(s.len()),
+				),
+			};
+			let filled = std::mem::replace(&mut self.buf, next);
+			self.full.push(filled);
+		}
 	}
 }
 
 impl fmt::Write for Appender {
+	#[inline]
 	fn write_str(&mut self, s: &str) -> fmt::Result {
-		self.assert_cap(s.len());
-		self.current().write_str(s)
+		if self.buf.capacity() - self.buf.len() >= s.len() {
+			self.buf += s;
+		} else {
+			self.write_overflowing(s);
+		}
+		Ok(())
+	}
+}
+
+// Maximum number of appenders retained in the pool
+const POOL_SIZE: usize = 8;
+
+// Appenders with more allocated memory are not returned to the pool, so a
+// single large render does not retain its memory indefinitely
+const POOL_MAX_CAPACITY: usize = 1 << 20;
+
+thread_local! {
+	// Appenders retained between renders and patches for reuse of their
+	// allocated memory
+	static APPENDERS: RefCell<Vec<Appender>> = Default::default();
+}
+
+// Appender taken from the thread-local pool. Cleared and returned to the pool
+// on drop.
+pub struct PooledAppender(Option<Appender>);
+
+impl std::ops::Deref for PooledAppender {
+	type Target = Appender;
+
+	fn deref(&self) -> &Appender {
+		self.0.as_ref().unwrap()
 	}
+}
 
-	fn write_char(&mut self, c: char) -> fmt::Result {
-		self.assert_cap(1);
-		self.current().write_char(c)
+impl std::ops::DerefMut for PooledAppender {
+	fn deref_mut(&mut self) -> &mut Appender {
+		self.0.as_mut().unwrap()
 	}
 }
 
+impl Drop for PooledAppender {
+	fn drop(&mut self) {
+		let mut w = match self.0.take() {
+			Some(w) => w,
+			None => return,
+		};
+		if w.capacity() > POOL_MAX_CAPACITY {
+			return;
+		}
+		w.clear();
+
+		// The pool may already be destroyed during thread shutdown
+		let _ = APPENDERS.try_with(|p| {
+			let mut p = p.borrow_mut();
+			if p.len() < POOL_SIZE {
+				p.push(w);
+			}
+		});
+	}
+}
+
+// Take an appender from the thread-local pool or create a new one, if the pool
+// is empty
+pub fn pooled_appender() -> PooledAppender {
+	PooledAppender(Some(
+		APPENDERS.with(|p| p.borrow_mut().pop()).unwrap_or_default(),
+	))
+}
+
 // Lazily retrieves an element by its ID
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct LazyElement {
 	pub id: u64,
 	pub element: Option<web_sys::Element>,
@@ -72,7 +269,14 @@ pub struct LazyElement {
 
 impl LazyElement {
 	// Retrieve JS element reference or cached value
-	pub fn get(&mut self) -> Result<web_sys::Element, JsValue> {
+	pub fn get(&mut self) -> Result<web_sys::Element, Error> {
+		#[cfg(test)]
+		{
+			if is_mock_dom() {
+				return Ok(mock_element());
+			}
+		}
+
 		match &mut self.element {
 			Some(el) => Ok(el.clone()),
 			None => {
@@ -81,13 +285,121 @@ impl LazyElement {
 						self.element = Some(el.clone());
 						Ok(el)
 					}
-					None => {
-						Err(format!("element not found: bh-{}", self.id).into())
-					}
+					None => Err(Error::ElementNotFound { id: self.id }),
 				}
 			}
 		}
 	}
+
+	// Like get(), but also checks, that a cached element is still in the
+	// document. Returns None, if the element is not found.
+	pub fn find(&mut self) -> Option<web_sys::Element> {
+		self.forget_detached();
+		self.get().ok()
+	}
+
+	// Drop cached element reference, if the element is no longer in the
+	// document
+	pub fn forget_detached(&mut self) {
+		if let Some(el) = &self.element {
+			if !el.is_connected() {
+				self.element = None;
+			}
+		}
+	}
+
+	// Drop cached element reference, so the element is looked up again
+	pub fn reset(&mut self) {
+		self.element = None;
+	}
+
+	// Insert HTML relative to the element
+	pub fn insert_adjacent_html(
+		&mut self,
+		position: &'static str,
+		html: &str,
+	) -> Result<(), Error> {
+		#[cfg(test)]
+		{
+			if record(|| DomOp::InsertHtml(self.id, position, html.into())) {
+				return Ok(());
+			}
+		}
+
+		self.get()?.insert_adjacent_html(position, html)?;
+		Ok(())
+	}
+
+	// Move the element of other relative to the element
+	pub fn insert_adjacent_element(
+		&mut self,
+		position: &'static str,
+		other: &mut LazyElement,
+	) -> Result<(), Error> {
+		#[cfg(test)]
+		{
+			if record(|| DomOp::Move(other.id, position, self.id)) {
+				return Ok(());
+			}
+		}
+
+		self.get()?
+			.insert_adjacent_element(position, &other.get()?)?;
+		Ok(())
+	}
+
+	// Remove the element from the DOM
+	pub fn remove(&mut self) -> Result<(), Error> {
+		#[cfg(test)]
+		{
+			if record(|| DomOp::Remove(self.id)) {
+				return Ok(());
+			}
+		}
+
+		self.get()?.remove();
+		Ok(())
+	}
+
+	// Replace the element and its subtree with HTML
+	pub fn set_outer_html(&mut self, html: &str) -> Result<(), Error> {
+		#[cfg(test)]
+		{
+			if record(|| DomOp::ReplaceHtml(self.id, html.into())) {
+				return Ok(());
+			}
+		}
+
+		self.get()?.set_outer_html(html);
+		Ok(())
+	}
+
+	// Return the element with input focus in the document, if it is the
+	// element or one of its descendants
+	pub fn active_within(&mut self) -> Result<Option<web_sys::Element>, Error> {
+		#[cfg(test)]
+		{
+			if is_mock_dom() {
+				return Ok(None);
+			}
+		}
+
+		let el = self.get()?;
+		Ok(document().active_element().filter(|a| el.contains(Some(a))))
+	}
+
+	// Set text content of the element
+	pub fn set_text_content(&mut self, text: &str) -> Result<(), Error> {
+		#[cfg(test)]
+		{
+			if record(|| DomOp::SetText(self.id, text.into())) {
+				return Ok(());
+			}
+		}
+
+		self.get()?.set_text_content(Some(text));
+		Ok(())
+	}
 }
 
 // Run function with global variable immutable access
@@ -112,58 +424,161 @@ where
 	global.with(|r| func(r.borrow_mut().borrow_mut()))
 }
 
-// Bidirectional lookup map for <usize,T> with no key (or value) removal
+// Bidirectional lookup map for <u32,T> with reference counting of tokens.
+// Values are only removed by explicit calls to remove().
 #[derive(Default)]
 pub struct TokenMap<T: Eq + Hash + Clone> {
-	forward: HashMap<u16, T>,
-	inverted: HashMap<T, u16>,
+	forward: HashMap<u32, TokenEntry<T>>,
+	inverted: HashMap<T, u32>,
+}
+
+// Value of a token in TokenMap with its reference count
+struct TokenEntry<T> {
+	value: T,
+	refs: usize,
+
+	// Token is queued for a sweep
+	queued: bool,
 }
 
 impl<T: Eq + Hash + Clone> TokenMap<T> {
 	// Get key token for a value, if it is in the map
-	pub fn get_token(&self, value: &T) -> Option<&u16> {
-		self.inverted.get(value)
+	pub fn get_token<Q>(&self, value: &Q) -> Option<u32>
+	where
+		T: Borrow<Q>,
+		Q: Eq + Hash + ?Sized,
+	{
+		self.inverted.get(value).copied()
 	}
 
 	// Get a reference to value from token, if it is in the map
-	pub fn get_value(&self, token: u16) -> &T {
-		self.forward.get(&token).expect("unset token lookup")
+	pub fn get_value(&self, token: u32) -> &T {
+		&self.forward.get(&token).expect("unset token lookup").value
 	}
 
-	// Insert new token and value into map
-	pub fn insert(&mut self, token: u16, value: T) {
-		self.forward.insert(token, value.clone());
+	// Insert new token and value into map with a reference count of zero
+	pub fn insert(&mut self, token: u32, value: T) {
+		self.forward.insert(
+			token,
+			TokenEntry {
+				value: value.clone(),
+				refs: 0,
+				queued: false,
+			},
+		);
 		self.inverted.insert(value, token);
 	}
+
+	// Remove token and its value from the map
+	pub fn remove(&mut self, token: u32) -> Option<T> {
+		let e = self.forward.remove(&token)?;
+		self.inverted.remove(&e.value);
+		Some(e.value)
+	}
+
+	// Increment reference count of token
+	pub fn increment(&mut self, token: u32) {
+		self.forward.get_mut(&token).expect("unset token lookup").refs += 1;
+	}
+
+	// Decrement reference count of token and return the new count
+	pub fn decrement(&mut self, token: u32) -> usize {
+		let e = self.forward.get_mut(&token).expect("unset token lookup");
+		e.refs -= 1;
+		e.refs
+	}
+
+	// Return reference count of token, if it is in the map
+	pub fn refs(&self, token: u32) -> Option<usize> {
+		self.forward.get(&token).map(|e| e.refs)
+	}
+
+	// Set the queued flag of token. Returns, if it was not set before.
+	pub fn queue(&mut self, token: u32) -> bool {
+		let e = self.forward.get_mut(&token).expect("unset token lookup");
+		!std::mem::replace(&mut e.queued, true)
+	}
+
+	// Clear the queued flag of token, if it is in the map
+	pub fn dequeue(&mut self, token: u32) {
+		if let Some(e) = self.forward.get_mut(&token) {
+			e.queued = false;
+		}
+	}
+
+	// Return number of tokens in the map
+	pub fn len(&self) -> usize {
+		self.forward.len()
+	}
+
+	// Iterate over all tokens and their values in the map
+	pub fn iter(&self) -> impl Iterator<Item = (u32, &T)> {
+		self.forward.iter().map(|(k, e)| (*k, &e.value))
+	}
 }
 
-// Generates u16 IDs with optional highest bit flagging
+// Generates u32 IDs with optional highest bit flagging.
+// IDs never overlap the flag bit. Released IDs are reused, before generating
+// new ones. Once the 31 bit ID space is exhausted and there are no released
+// IDs, no more IDs are generated.
 #[derive(Default)]
 pub struct IDGenerator {
-	counter: u16,
+	counter: u32,
+	released: Vec<u32>,
 }
 
 impl IDGenerator {
-	pub fn new(start_from: u16) -> Self {
+	// Highest ID, that can be generated
+	pub const MAX: u32 = !IDGenerator::FLAG;
+
+	// Bit used for flagging IDs
+	const FLAG: u32 = 1 << 31;
+
+	pub fn new(start_from: u32) -> Self {
 		Self {
 			counter: start_from,
+			released: Vec::new(),
 		}
 	}
 
-	// Create new ID  with optional highest bit flagging
-	pub fn new_id(&mut self, flag_highest: bool) -> u16 {
-		self.counter += 1;
-		let mut id = self.counter;
+	// Restore generator from the state returned by state()
+	pub fn from_state(counter: u32, released: Vec<u32>) -> Self {
+		Self { counter, released }
+	}
+
+	// Return the last generated ID and released IDs pending reuse
+	pub fn state(&self) -> (u32, &[u32]) {
+		(self.counter, &self.released)
+	}
+
+	// Create new ID with optional highest bit flagging.
+	// Returns None, if the ID space has been exhausted.
+	pub fn new_id(&mut self, flag_highest: bool) -> Option<u32> {
+		let mut id = match self.released.pop() {
+			Some(id) => id,
+			None => {
+				if self.counter >= Self::MAX {
+					return None;
+				}
+				self.counter += 1;
+				self.counter
+			}
+		};
 		if flag_highest {
-			id |= 1 << 15;
+			id |= Self::FLAG;
 		}
-		return id;
+		Some(id)
+	}
+
+	// Release ID for reuse by new_id()
+	pub fn release(&mut self, id: u32) {
+		self.released.push(id & !Self::FLAG);
 	}
 
 	// Shorthand for checking highest bit being flagged
 	#[inline]
-	pub fn is_flagged(id: u16) -> bool {
-		return id & (1 << 15) != 0;
+	pub fn is_flagged(id: u32) -> bool {
+		id & Self::FLAG != 0
 	}
 }
 
@@ -193,6 +608,40 @@ pub fn html_escape(s: &str) -> String {
 	escaped
 }
 
+// Write an attribute value including the leading '=' as HTML to w. Escapes
+// '&', '"' and '<'. If minify is set, quotes are omitted from values, that
+// need neither quoting nor escaping.
+//
+// html! applies the same escaping to attribute values of hoisted subtrees.
+pub fn write_attr_value<W: fmt::Write>(
+	w: &mut W,
+	s: &str,
+	minify: bool,
+) -> fmt::Result {
+	let needs_quotes = |c: char| {
+		c.is_ascii_whitespace()
+			|| matches!(c, '"' | '\'' | '=' | '<' | '>' | '`' | '&')
+	};
+	if minify && !s.contains(needs_quotes) {
+		w.write_char('=')?;
+		return w.write_str(s);
+	}
+
+	w.write_str("=\"")?;
+	let mut rest = s;
+	while let Some(i) = rest.find(['&', '"', '<']) {
+		w.write_str(&rest[..i])?;
+		w.write_str(match rest.as_bytes()[i] {
+			b'&' => "&amp;",
+			b'"' => "&#34;",
+			_ => "&lt;",
+		})?;
+		rest = &rest[i + 1..];
+	}
+	w.write_str(rest)?;
+	w.write_char('"')
+}
+
 // Get JS window global
 pub fn window() -> web_sys::Window {
 	web_sys::window().expect("no window global")
@@ -203,12 +652,238 @@ pub fn document() -> web_sys::Document {
 	window().document().expect("no document on window")
 }
 
-// Cast Rust error to JSValue to be thrown as exception
-pub fn cast_error<T: Display>(e: T) -> JsValue {
-	JsValue::from(format!("{}", e))
-}
-
 // Able to write itself as HTML to w
 pub trait WriteHTMLTo {
 	fn write_html_to<W: fmt::Write>(&mut self, w: &mut W) -> fmt::Result;
 }
+
+// Global allocator counting allocations per thread, so tests running in
+// parallel do not affect each other's counts
+#[cfg(test)]
+struct CountingAllocator;
+
+#[cfg(test)]
+thread_local! {
+	static ALLOCATIONS: std::cell::Cell<usize> =
+		const { std::cell::Cell::new(0) };
+}
+
+#[cfg(test)]
+impl CountingAllocator {
+	fn count() {
+		// Thread-local storage may be unavailable during thread shutdown
+		let _ = ALLOCATIONS.try_with(|c| c.set(c.get() + 1));
+	}
+}
+
+#[cfg(test)]
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+	unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+		Self::count();
+		std::alloc::System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+		std::alloc::System.dealloc(ptr, layout)
+	}
+
+	unsafe fn realloc(
+		&self,
+		ptr: *mut u8,
+		layout: std::alloc::Layout,
+		new_size: usize,
+	) -> *mut u8 {
+		Self::count();
+		std::alloc::System.realloc(ptr, layout, new_size)
+	}
+}
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Return number of allocations and reallocations made by f on this thread
+#[cfg(test)]
+pub(crate) fn count_allocations<F: FnOnce()>(f: F) -> usize {
+	let start = ALLOCATIONS.with(|c| c.get());
+	f();
+	ALLOCATIONS.with(|c| c.get()) - start
+}
+
+// DOM operation recorded by the mock DOM instead of being performed
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DomOp {
+	// Element ID, position and inserted HTML
+	InsertHtml(u64, &'static str, String),
+
+	// ID of the moved element, position and ID of the target element
+	Move(u64, &'static str, u64),
+
+	// Element ID
+	Remove(u64),
+
+	// Element ID and HTML replacing the element
+	ReplaceHtml(u64, String),
+
+	// Element ID and new text content
+	SetText(u64, String),
+}
+
+#[cfg(test)]
+thread_local! {
+	static MOCK_DOM: RefCell<Option<Vec<DomOp>>> = const { RefCell::new(None) };
+}
+
+#[cfg(test)]
+fn is_mock_dom() -> bool {
+	MOCK_DOM.with(|m| m.borrow().is_some())
+}
+
+// Record DOM operation, if the mock DOM is enabled. Returns, if recorded.
+#[cfg(test)]
+fn record<F: FnOnce() -> DomOp>(op: F) -> bool {
+	MOCK_DOM.with(|m| match &mut *m.borrow_mut() {
+		Some(ops) => {
+			ops.push(op());
+			true
+		}
+		None => false,
+	})
+}
+
+// Placeholder element returned for every lookup under the mock DOM. Must not be
+// cloned or have its methods called natively.
+#[cfg(test)]
+fn mock_element() -> web_sys::Element {
+	use wasm_bindgen::JsCast;
+
+	wasm_bindgen::JsValue::NULL.unchecked_into()
+}
+
+// Run f with DOM operations of LazyElement recorded instead of performed and
+// every element lookup succeeding. Returns the recorded operations.
+#[cfg(test)]
+pub(crate) fn mock_dom<F, R>(f: F) -> (R, Vec<DomOp>)
+where
+	F: FnOnce() -> R,
+{
+	MOCK_DOM.with(|m| *m.borrow_mut() = Some(Vec::new()));
+	let res = f();
+	let ops = MOCK_DOM.with(|m| m.borrow_mut().take()).unwrap_or_default();
+	(res, ops)
+}
+
+#[test]
+fn attr_value_escaping() {
+	let write = |s: &str, minify: bool| {
+		let mut w = String::new();
+		write_attr_value(&mut w, s, minify).unwrap();
+		w
+	};
+	assert_eq!(write("a b", false), r#"="a b""#);
+	assert_eq!(write("ab", true), "=ab");
+	assert_eq!(write("a b", true), r#"="a b""#);
+	assert_eq!(
+		write(r#"" onclick="alert(1)"#, true),
+		r#"="&#34; onclick=&#34;alert(1)""#
+	);
+	assert_eq!(write("a&b<c>'", false), r#"="a&amp;b&lt;c>'""#);
+	assert_eq!(write("a&amp;", true), r#"="a&amp;amp;""#);
+}
+
+#[test]
+fn appender_growth() {
+	use fmt::Write;
+
+	let mut w = Appender::new();
+	let small = "a".repeat(100);
+	let large = "ä".repeat(1000);
+	let mut expected = String::new();
+	for s in [small.as_str(), &large, "ö", &small, &large].iter() {
+		w.write_str(s).unwrap();
+		expected += s;
+	}
+	assert_eq!(w.len(), expected.len());
+	assert_eq!(w.chunks().collect::<String>(), expected);
+
+	// Every chunk, but the last one, is filled up to a character boundary
+	assert!(!w.full.is_empty());
+	for c in w.full.iter() {
+		assert!(c.capacity() - c.len() < 2);
+	}
+
+	// Reuse after clear with a write larger than any remaining chunk
+	let allocated = w.full.len() + 1;
+	w.clear();
+	assert!(w.is_empty());
+	assert_eq!(w.chunks().count(), 0);
+	let huge = "b".repeat(10_000);
+	write!(w, "{}{}", small, huge).unwrap();
+	assert_eq!(w.dump(), small.clone() + &huge);
+	assert!(w.full.len() + 1 >= allocated);
+
+	let mut dst = String::from("x");
+	w.dump_into(&mut dst);
+	assert_eq!(dst, "x".to_string() + &small + &huge);
+	assert_eq!(w.into_string(), small + &huge);
+}
+
+#[test]
+fn appender_single_chunk() {
+	use fmt::Write;
+
+	let mut w = Appender::new();
+	w.write_str("abc").unwrap();
+	let ptr = w.chunks().next().unwrap().as_ptr();
+	let s = w.into_string();
+	assert_eq!(s, "abc");
+
+	// Not copied
+	assert_eq!(s.as_ptr(), ptr);
+
+	// Taken out of a reused appender
+	let mut w = Appender::new();
+	w.write_str("abc").unwrap();
+	let ptr = w.chunks().next().unwrap().as_ptr();
+	let s = w.take_string();
+	assert_eq!(s, "abc");
+	assert_eq!(s.as_ptr(), ptr);
+	assert!(w.is_empty());
+	w.write_str("def").unwrap();
+	assert_eq!(w.take_string(), "def");
+
+	// Copied from multiple chunks
+	let large = "x".repeat(1000);
+	w.write_str(&large).unwrap();
+	assert_eq!(w.take_string(), large);
+	assert!(w.is_empty());
+}
+
+#[test]
+fn appender_pool() {
+	use fmt::Write;
+
+	let render = || {
+		let mut w = pooled_appender();
+		for _ in 0..1000 {
+			w.write_str("<div class=\"item\">text</div>").unwrap();
+		}
+		assert_eq!(w.join().len(), 1000 * 28);
+	};
+
+	// Warm up the pool
+	render();
+	assert_eq!(count_allocations(render), 0);
+
+	// Nested use takes separate appenders
+	render();
+	let nested = || {
+		let mut w = pooled_appender();
+		w.write_str("outer").unwrap();
+		render();
+		assert_eq!(w.join(), "outer");
+	};
+	nested();
+	assert_eq!(count_allocations(nested), 0);
+}